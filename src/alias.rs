@@ -0,0 +1,348 @@
+// Match hosts against app names, aliases and alias patterns
+use std::convert::TryFrom;
+use std::fmt;
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::config;
+
+/// Entry in an app's `aliases` list, matched against the host without its TLD
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "String")]
+pub enum Alias {
+    /// Name matched as is, along with its subdomains
+    Exact(String),
+    /// Name where each `*` matches any part of a single label, like "*.example" or "shop-*"
+    Wildcard(String, Regex),
+    /// Regular expression written as "~pattern", which has to match the whole name
+    Regex(String, Regex),
+}
+
+/// How a host matched, when several apps match the one ranked highest wins
+///
+/// Exact names beat wildcards, which beat subdomains of a name, which beat regexes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Precedence {
+    Regex,
+    /// Ranked by the length of the name, so the deepest one wins
+    Subdomain(usize),
+    /// Ranked by the number of characters that aren't wildcards
+    Wildcard(usize),
+    Exact,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct HostMatch<'a> {
+    pub precedence: Precedence,
+    /// Labels in front of a name, or what the first wildcard or regex group matched
+    pub subdomain: Option<&'a str>,
+}
+
+impl TryFrom<String> for Alias {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        if let Some(pattern) = source.strip_prefix('~') {
+            let regex = compile(&format!("^(?:{})$", pattern))
+                .map_err(|e| format!("invalid alias pattern {:?}: {}", source, e))?;
+
+            return Ok(Self::Regex(source, regex));
+        }
+
+        let source = source.trim_matches('.').to_string();
+
+        if source.is_empty() || source.contains(|c: char| c.is_whitespace() || c == ':') {
+            return Err(format!("invalid alias {:?}", source));
+        }
+
+        if source.contains('*') {
+            let pattern = source
+                .split('*')
+                .map(regex::escape)
+                .collect::<Vec<_>>()
+                .join("([^.]*)");
+            let regex = compile(&format!("^{}$", pattern)).map_err(|e| e.to_string())?;
+
+            return Ok(Self::Wildcard(source, regex));
+        }
+
+        Ok(Self::Exact(source.to_ascii_lowercase()))
+    }
+}
+
+fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    regex::RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+}
+
+impl Alias {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Exact(source) | Self::Wildcard(source, _) | Self::Regex(source, _) => source,
+        }
+    }
+
+    /// Match a host with its TLD and port removed, e.g. "acme.myapp" for "acme.myapp.test"
+    pub fn matches<'a>(&self, name: &'a str) -> Option<HostMatch<'a>> {
+        match self {
+            Self::Exact(alias) => match_exact(alias, name),
+            Self::Wildcard(source, regex) => {
+                let literal = source.chars().filter(|&c| c != '*').count();
+                let captures = regex.captures(name)?;
+
+                Some(HostMatch {
+                    precedence: Precedence::Wildcard(literal),
+                    subdomain: captures.get(1).map(|group| group.as_str()),
+                })
+            }
+            Self::Regex(_, regex) => {
+                let captures = regex.captures(name)?;
+                let group = captures.name("subdomain").or_else(|| captures.get(1));
+
+                Some(HostMatch {
+                    precedence: Precedence::Regex,
+                    subdomain: group.map(|group| group.as_str()),
+                })
+            }
+        }
+    }
+}
+
+impl PartialEq for Alias {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Alias {}
+
+impl fmt::Display for Alias {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Match `name` against an app name or exact alias, or a subdomain of it
+pub fn match_exact<'a>(domain: &str, name: &'a str) -> Option<HostMatch<'a>> {
+    if name.eq_ignore_ascii_case(domain) {
+        return Some(HostMatch {
+            precedence: Precedence::Exact,
+            subdomain: None,
+        });
+    }
+
+    let split = name.len().checked_sub(domain.len() + 1)?;
+    let (subdomain, rest) = (name.get(..split)?, name.get(split..)?);
+
+    if !subdomain.is_empty() && rest.starts_with('.') && rest[1..].eq_ignore_ascii_case(domain) {
+        Some(HostMatch {
+            precedence: Precedence::Subdomain(domain.len()),
+            subdomain: Some(subdomain),
+        })
+    } else {
+        None
+    }
+}
+
+/// Best match for `name` among an app's name and aliases
+pub fn best_match<'a>(app_name: &str, aliases: &[Alias], name: &'a str) -> Option<HostMatch<'a>> {
+    let aliased = aliases.iter().filter_map(|alias| alias.matches(name));
+
+    match_exact(app_name, name)
+        .into_iter()
+        .chain(aliased)
+        .max_by_key(|found| found.precedence)
+}
+
+/// Describe aliases of different apps that match the same hosts with the same precedence
+///
+/// Exact names and wildcards are compared fully, regexes only when they're identical. Ties go
+/// to the app whose name sorts first.
+pub fn conflicts(apps: &[config::App], default_tlds: &[String]) -> Vec<String> {
+    let mut apps: Vec<&config::App> = apps.iter().collect();
+    apps.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut conflicts = Vec::new();
+
+    for (i, first) in apps.iter().enumerate() {
+        for second in &apps[i + 1..] {
+            let tlds = first.tlds_or(default_tlds);
+            if !second
+                .tlds_or(default_tlds)
+                .iter()
+                .any(|tld| tlds.contains(tld))
+            {
+                continue;
+            }
+
+            for first_alias in patterns(first) {
+                for second_alias in patterns(second) {
+                    if overlap(&first_alias, &second_alias) {
+                        conflicts.push(format!(
+                            "{:?} of app {} and {:?} of app {} match the same hosts, {} takes precedence",
+                            first_alias.as_str(),
+                            first.name,
+                            second_alias.as_str(),
+                            second.name,
+                            first.name
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    conflicts
+}
+
+fn patterns(app: &config::App) -> impl Iterator<Item = Alias> + '_ {
+    std::iter::once(Alias::Exact(app.name.to_ascii_lowercase())).chain(app.aliases.iter().cloned())
+}
+
+/// Check if two aliases with the same precedence can match the same host
+fn overlap(first: &Alias, second: &Alias) -> bool {
+    match (first, second) {
+        (Alias::Exact(a), Alias::Exact(b)) => a == b,
+        (Alias::Wildcard(a, _), Alias::Wildcard(b, _)) => {
+            let literal = |source: &str| source.chars().filter(|&c| c != '*').count();
+
+            literal(a) == literal(b)
+                && wildcards_overlap(
+                    &a.to_ascii_lowercase().into_bytes(),
+                    &b.to_ascii_lowercase().into_bytes(),
+                )
+        }
+        (Alias::Regex(a, _), Alias::Regex(b, _)) => a == b,
+        _ => false,
+    }
+}
+
+/// Check if some name matches both patterns, where `*` matches anything but a dot
+fn wildcards_overlap(a: &[u8], b: &[u8]) -> bool {
+    match (a.split_first(), b.split_first()) {
+        (None, None) => true,
+        (Some((b'*', a_rest)), _) => {
+            wildcards_overlap(a_rest, b)
+                || b.first().is_some_and(|&c| c != b'.') && wildcards_overlap(a, &b[1..])
+        }
+        (_, Some((b'*', b_rest))) => {
+            wildcards_overlap(a, b_rest)
+                || a.first().is_some_and(|&c| c != b'.') && wildcards_overlap(&a[1..], b)
+        }
+        (Some((x, a_rest)), Some((y, b_rest))) => x == y && wildcards_overlap(a_rest, b_rest),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alias(source: &str) -> Alias {
+        Alias::try_from(source.to_string()).unwrap()
+    }
+
+    fn app(name: &str, aliases: &[&str]) -> config::App {
+        config::App {
+            name: name.to_string(),
+            aliases: aliases.iter().map(|source| alias(source)).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn matches_aliases() {
+        let exact = alias("Shop.Example");
+        assert_eq!(
+            Precedence::Exact,
+            exact.matches("shop.example").unwrap().precedence
+        );
+        assert_eq!(
+            Some(HostMatch {
+                precedence: Precedence::Subdomain(12),
+                subdomain: Some("a.b"),
+            }),
+            exact.matches("a.b.shop.example")
+        );
+        assert!(exact.matches("myshop.example").is_none());
+
+        let wildcard = alias("*.example");
+        assert_eq!(
+            Some(HostMatch {
+                precedence: Precedence::Wildcard(8),
+                subdomain: Some("Shop"),
+            }),
+            wildcard.matches("Shop.example")
+        );
+        assert!(wildcard.matches("a.b.example").is_none());
+        assert!(wildcard.matches("example").is_none());
+
+        let regex = alias("~(?P<subdomain>[a-z]+)-shop\\.(example|acme)");
+        assert_eq!(
+            Some(HostMatch {
+                precedence: Precedence::Regex,
+                subdomain: Some("globex"),
+            }),
+            regex.matches("globex-shop.acme")
+        );
+        assert!(regex.matches("globex-shop.acme.other").is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_aliases() {
+        assert!(Alias::try_from("~(unclosed".to_string()).is_err());
+        assert!(Alias::try_from("two words".to_string()).is_err());
+        assert!(Alias::try_from("".to_string()).is_err());
+    }
+
+    #[test]
+    fn prefers_more_specific_matches() {
+        assert!(Precedence::Exact > Precedence::Wildcard(20));
+        assert!(Precedence::Wildcard(8) > Precedence::Wildcard(2));
+        assert!(Precedence::Wildcard(1) > Precedence::Subdomain(20));
+        assert!(Precedence::Subdomain(1) > Precedence::Regex);
+
+        let aliases = [alias("*.myapp"), alias("~.*")];
+        assert_eq!(
+            Precedence::Exact,
+            best_match("myapp", &aliases, "myapp").unwrap().precedence
+        );
+        assert_eq!(
+            Precedence::Wildcard(6),
+            best_match("myapp", &aliases, "acme.myapp")
+                .unwrap()
+                .precedence
+        );
+        assert_eq!(
+            Precedence::Regex,
+            best_match("myapp", &aliases, "other").unwrap().precedence
+        );
+    }
+
+    #[test]
+    fn detects_conflicts() {
+        let apps = [
+            app("shop", &["*.example", "store", "~tenant-.*"]),
+            app("store", &["shop-*.example"]),
+            app("other", &["*.ex*ample", "~tenant-.*"]),
+            app("unrelated", &["*.example.com"]),
+        ];
+        let conflicts = conflicts(&apps, &["test".to_string()]);
+
+        assert_eq!(3, conflicts.len(), "{:?}", conflicts);
+        assert!(conflicts[0].contains("\"*.ex*ample\" of app other and \"*.example\" of app shop"));
+        assert!(conflicts[1].contains("\"~tenant-.*\" of app other"));
+        assert!(conflicts[2].contains("\"store\" of app shop and \"store\" of app store"));
+    }
+
+    #[test]
+    fn ignores_apps_on_other_tlds() {
+        let mut other = app("other", &["*.example"]);
+        other.tlds = Some(vec!["acme".to_string()]);
+        let apps = [app("shop", &["*.example"]), other];
+
+        assert!(conflicts(&apps, &["test".to_string()]).is_empty());
+    }
+}
@@ -1,15 +1,23 @@
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use futures::future::join_all;
 use futures::Stream;
 use tokio::sync::RwLock;
 
+use crate::alias::Alias;
+use crate::backend::Backend;
 use crate::config;
-use crate::process::Process;
+use crate::faults::Faults;
+use crate::header_rules::HeaderRules;
+use crate::inspector::Inspector;
+use crate::process::{Process, Trigger};
 
 // Follow Heroku convention of "web" as the label for primary process
 const DEFAULT_PROCESS: &str = "web";
+const DEFAULT_START_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_RESPONSE_TIMEOUT_SECS: u64 = 120;
 
 #[derive(Clone, Debug)]
 pub struct App {
@@ -19,39 +27,124 @@ pub struct App {
     port: u16,
     /// App working directory
     directory: String,
-    command_config: config::CommandConfig,
-    /// Headers added to proxied request to the app
-    headers: hyper::HeaderMap,
+    /// Header rewriting for requests to the app
+    request_headers: HeaderRules,
+    /// Header rewriting for responses from the app
+    response_headers: HeaderRules,
     /// List of processes for this app
     pub processes: Vec<Process>,
-    /// Domain TLD suffix, defaults to ".test"
-    tld: String,
-    /// Alternate domain names for app
-    aliases: Vec<String>,
+    /// TLDs the app is served under
+    tlds: Vec<String>,
+    /// Alternate domain names and patterns for app
+    aliases: Vec<Alias>,
     /// Last time app was accessed
     last_hit: Arc<RwLock<Instant>>,
+    /// Path prefixes served by other processes or apps
+    routes: Vec<config::Route>,
+    /// Where requests go for apps that don't run any processes
+    fixed_backend: Option<Backend>,
+    /// Number of ports reserved from the auto assigned range
+    allocated_ports: u16,
+    /// Whether processes have their own ports and are reached by subdomain
+    per_process_ports: bool,
+    /// Hold requests while the app is starting rather than returning the autostart page
+    wait_for_start: bool,
+    /// Deadline for the app to start accepting connections
+    start_timeout: Duration,
+    /// Deadline for the app to respond to a request
+    response_timeout: Duration,
+    /// Protocol used to talk to the app's processes
+    upstream_protocol: config::UpstreamProtocol,
+    /// Root directory for FastCGI scripts and files
+    document_root: PathBuf,
+    /// Which forwarding headers are added to proxied requests
+    forwarded_headers: config::ForwardedHeaders,
+    /// Whether the Host header is passed through or rewritten
+    host_header: Option<config::HostHeader>,
+    /// Which backend URLs in responses are pointed back at the app's domain
+    rewrite_urls: config::RewriteUrls,
+    /// Recently captured requests
+    inspector: Arc<Inspector>,
+    /// Simulated failures, shared so they can be toggled at runtime
+    faults: Arc<Faults>,
 }
 
 impl App {
-    pub fn from_config(app_config: &config::App, auto_port: u16, tld: String) -> Self {
+    pub fn from_config(
+        app_config: &config::App,
+        auto_port: u16,
+        general: &config::ProxyConfig,
+    ) -> Self {
         let port = app_config.port.unwrap_or(auto_port);
 
-        let processes = app_config
-            .commands()
+        let mut commands: Vec<_> = app_config.commands().into_iter().collect();
+        commands.sort();
+
+        let default_index = commands
+            .iter()
+            .position(|(name, _)| name == DEFAULT_PROCESS)
+            .unwrap_or(0);
+
+        // Processes other than the default get ports following the app's auto assigned port
+        let mut extra_ports = auto_port + 1..;
+        let processes = commands
             .into_iter()
-            .map(|(name, command)| Process::from_config(app_config, name, command, port))
+            .enumerate()
+            .map(|(index, (name, command))| {
+                let process_port = if app_config.per_process_ports && index != default_index {
+                    extra_ports.next().unwrap()
+                } else {
+                    port
+                };
+
+                Process::from_config(app_config, name, command, process_port, general)
+            })
             .collect();
+        let allocated_ports = extra_ports.start - auto_port;
 
         Self {
             name: app_config.name.clone(),
             port,
-            command_config: app_config.command_config.clone(),
             directory: app_config.full_path(),
-            headers: app_config.parsed_headers(),
+            request_headers: HeaderRules::from_fixed(&app_config.parsed_headers())
+                .merge(&app_config.request_headers),
+            response_headers: app_config.response_headers.clone(),
             processes,
-            tld,
+            tlds: app_config.tlds_or(&general.domains).to_vec(),
             aliases: app_config.aliases.clone(),
             last_hit: Arc::new(RwLock::new(Instant::now())),
+            routes: app_config.routes.clone(),
+            fixed_backend: match &app_config.command_config {
+                config::CommandConfig::Upstream(url) => Some(Backend::External {
+                    url: url.clone(),
+                    insecure: app_config.insecure_upstream,
+                }),
+                config::CommandConfig::Static => Some(Backend::Static {
+                    root: app_config.full_path().into(),
+                    spa_fallback: app_config.spa_fallback,
+                }),
+                _ => None,
+            },
+            allocated_ports,
+            per_process_ports: app_config.per_process_ports,
+            wait_for_start: app_config.wait_for_start,
+            start_timeout: Duration::from_secs(
+                app_config
+                    .start_timeout_secs
+                    .unwrap_or(DEFAULT_START_TIMEOUT_SECS),
+            ),
+            response_timeout: Duration::from_secs(
+                app_config
+                    .response_timeout_secs
+                    .unwrap_or(DEFAULT_RESPONSE_TIMEOUT_SECS),
+            ),
+            upstream_protocol: app_config.upstream_protocol,
+            document_root: app_config.full_document_root(),
+            forwarded_headers: app_config.forwarded_headers.clone(),
+            host_header: app_config.host_header,
+            rewrite_urls: app_config.rewrite_urls.clone(),
+            inspector: Arc::new(Inspector::new(app_config.capture.clone())),
+            faults: Arc::new(Faults::new(&app_config.faults)),
         }
     }
 
@@ -85,6 +178,47 @@ impl App {
         }
     }
 
+    /// Stop the app because it hasn't been requested in a while
+    pub(crate) async fn stop_idle(&self) {
+        for process in &self.processes {
+            process.stop_for(Trigger::IdleStop).await
+        }
+    }
+
+    /// Recent runs of each process, or just the named one
+    pub(crate) async fn history(&self, process_name: Option<&str>) -> Option<String> {
+        let mut report = String::new();
+
+        for process in &self.processes {
+            let name = process.process_name().await;
+            if process_name.is_some_and(|wanted| wanted != name) {
+                continue;
+            }
+
+            report.push_str(&format!("{}:\n", process.name().await));
+            for run in process.history().await {
+                report.push_str(&run.details());
+            }
+        }
+
+        Some(report).filter(|report| !report.is_empty())
+    }
+
+    /// Explanation of a process that exited on its own and hasn't been started since
+    pub(crate) async fn last_crash(&self) -> Option<String> {
+        for process in &self.processes {
+            if let Some(run) = process.last_crash().await {
+                return Some(format!(
+                    "{} exited unexpectedly\n{}",
+                    process.name().await,
+                    run.details()
+                ));
+            }
+        }
+
+        None
+    }
+
     pub async fn is_running(&self) -> bool {
         for process in &self.processes {
             if process.is_running().await {
@@ -95,8 +229,36 @@ impl App {
         false
     }
 
-    pub fn headers(&self) -> &hyper::HeaderMap {
-        &self.headers
+    /// Name and automatic restart count of a process that's crash looping
+    pub(crate) async fn crash_looping(&self) -> Option<(String, usize)> {
+        for process in &self.processes {
+            if let Some(restarts) = process.crash_loop_restarts().await {
+                return Some((process.name().await, restarts));
+            }
+        }
+
+        None
+    }
+
+    /// Shortest delay before a process that exited is started again
+    pub(crate) async fn restart_delay(&self) -> Option<Duration> {
+        let mut shortest = None;
+
+        for process in &self.processes {
+            if let Some(delay) = process.restart_delay().await {
+                shortest = Some(shortest.map_or(delay, |shortest: Duration| shortest.min(delay)));
+            }
+        }
+
+        shortest
+    }
+
+    pub fn request_headers(&self) -> &HeaderRules {
+        &self.request_headers
+    }
+
+    pub fn response_headers(&self) -> &HeaderRules {
+        &self.response_headers
     }
 
     pub async fn default_process(&self) -> Option<&Process> {
@@ -115,8 +277,116 @@ impl App {
         None
     }
 
+    /// Name of the process listening on `backend`, if it's one of this app's
+    pub(crate) async fn process_name_for(&self, backend: &Backend) -> Option<String> {
+        for process in &self.processes {
+            if &process.backend().await == backend {
+                return Some(process.process_name().await);
+            }
+        }
+
+        None
+    }
+
+    /// Backend of the process named by the last subdomain label, defaulting to the app's backend
+    ///
+    /// For example "webpack.myapp.test" is routed to the "webpack" process if it exists and
+    /// processes have their own ports.
+    pub(crate) async fn backend_for_subdomain(&self, subdomain: Option<&str>) -> Backend {
+        let process_name = subdomain
+            .filter(|_| self.per_process_ports)
+            .and_then(|subdomain| subdomain.rsplit('.').next());
+
+        if let Some(name) = process_name {
+            if let Some(process) = self.find_process(name).await {
+                return process.backend().await;
+            }
+        }
+
+        self.default_backend().await
+    }
+
+    /// Backend of the default process, or the fixed backend for external and static apps
+    pub(crate) async fn default_backend(&self) -> Backend {
+        if let Some(backend) = &self.fixed_backend {
+            return backend.clone();
+        }
+
+        match self.default_process().await {
+            Some(process) => process.backend().await,
+            None => Backend::Port(self.port),
+        }
+    }
+
+    /// Most specific route matching the request path
+    pub fn find_route(&self, path: &str) -> Option<&config::Route> {
+        self.routes
+            .iter()
+            .filter(|route| route.matches(path))
+            .max_by_key(|route| route.prefix.trim_end_matches('/').len())
+    }
+
+    /// Whether the app runs outside of oxidux, with no processes to manage
+    pub fn is_external(&self) -> bool {
+        matches!(self.fixed_backend, Some(Backend::External { .. }))
+    }
+
+    /// Whether the app is a directory served by the proxy
+    pub fn is_static(&self) -> bool {
+        matches!(self.fixed_backend, Some(Backend::Static { .. }))
+    }
+
+    pub(crate) fn allocated_ports(&self) -> u16 {
+        self.allocated_ports
+    }
+
+    pub fn wait_for_start(&self) -> bool {
+        self.wait_for_start
+    }
+
+    pub fn start_timeout(&self) -> Duration {
+        self.start_timeout
+    }
+
+    pub fn response_timeout(&self) -> Duration {
+        self.response_timeout
+    }
+
+    pub fn upstream_protocol(&self) -> config::UpstreamProtocol {
+        self.upstream_protocol
+    }
+
+    pub fn document_root(&self) -> &Path {
+        &self.document_root
+    }
+
+    pub fn forwarded_headers(&self) -> &config::ForwardedHeaders {
+        &self.forwarded_headers
+    }
+
+    pub fn host_header(&self) -> Option<config::HostHeader> {
+        self.host_header
+    }
+
+    pub fn rewrite_urls(&self) -> &config::RewriteUrls {
+        &self.rewrite_urls
+    }
+
+    pub fn inspector(&self) -> &Inspector {
+        &self.inspector
+    }
+
+    pub fn faults(&self) -> &Faults {
+        &self.faults
+    }
+
+    /// Main TLD, used for links to the app
     pub fn tld(&self) -> &str {
-        &self.tld
+        &self.tlds[0]
+    }
+
+    pub fn tlds(&self) -> &[String] {
+        &self.tlds
     }
 
     pub async fn output_stream(&self) -> impl Stream<Item = (Process, String)> {
@@ -127,8 +397,8 @@ impl App {
         futures::stream::select_all(streams)
     }
 
-    pub fn domains(&self) -> impl Iterator<Item = &String> + '_ {
-        std::iter::once(&self.name).chain(self.aliases.iter())
+    pub(crate) fn aliases(&self) -> &[Alias] {
+        &self.aliases
     }
 
     /// Last time app was accessed
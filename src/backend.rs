@@ -0,0 +1,70 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use tokio::net::{TcpStream, UnixStream};
+use url::Url;
+
+/// Address the proxy forwards app requests to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// TCP port on localhost
+    Port(u16),
+    /// Path to a Unix domain socket
+    Socket(PathBuf),
+    /// Server running outside of oxidux
+    External { url: Url, insecure: bool },
+    /// Directory served directly by the proxy
+    Static { root: PathBuf, spa_fallback: bool },
+}
+
+impl Backend {
+    /// Check if the app is accepting connections on this address
+    pub(crate) async fn is_listening(&self) -> bool {
+        match self {
+            Backend::Port(port) => TcpStream::connect(("localhost", *port)).await.is_ok(),
+            Backend::Socket(path) => UnixStream::connect(path).await.is_ok(),
+            Backend::External { url, .. } => match (url.host_str(), url.port_or_known_default()) {
+                (Some(host), Some(port)) => TcpStream::connect((host, port)).await.is_ok(),
+                _ => false,
+            },
+            Backend::Static { .. } => true,
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Port(port) => write!(f, "localhost:{}", port),
+            Backend::Socket(path) => write!(f, "unix:{}", path.display()),
+            Backend::External { url, .. } => write!(f, "{}", url),
+            Backend::Static { root, .. } => write!(f, "static:{}", root.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    #[tokio::test]
+    async fn socket_listening() {
+        let tmp = test_utils::temp_dir();
+        let path = tmp.join("app.sock");
+        let backend = Backend::Socket(path.clone());
+
+        assert!(!backend.is_listening().await);
+
+        let _listener = tokio::net::UnixListener::bind(&path).unwrap();
+        assert!(backend.is_listening().await);
+    }
+
+    #[tokio::test]
+    async fn port_listening() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let backend = Backend::Port(listener.local_addr().unwrap().port());
+
+        assert!(backend.is_listening().await);
+    }
+}
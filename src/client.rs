@@ -1,6 +1,10 @@
 use eyre::{bail, eyre, Context};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::termios::{self, SetArg, Termios};
+use nix::unistd;
 use std::env;
-use std::io::Write;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::io::AsRawFd;
 use std::os::unix::net::UnixStream;
 use std::process::Command;
 use std::time::Duration;
@@ -8,10 +12,14 @@ use std::time::Duration;
 use crate::config;
 use crate::ipc_command::IpcCommand;
 use crate::ipc_response::IpcResponse;
+use crate::tls;
 
 type ClientResult<T> = color_eyre::Result<T>;
 type EmptyResult = ClientResult<()>;
 
+/// Ctrl-X, which disconnects from a process like it does in tmux
+const DETACH_KEY: u8 = 0x18;
+
 pub fn restart_process(process_name: Option<&str>) -> EmptyResult {
     let command = IpcCommand::restart_command(process_name.map(str::to_string), current_dir()?);
     send_command(&command)?;
@@ -19,7 +27,11 @@ pub fn restart_process(process_name: Option<&str>) -> EmptyResult {
 }
 
 pub fn connect_to_process(process_name: Option<&str>) -> EmptyResult {
-    let command = IpcCommand::connect_command(process_name.map(str::to_string), current_dir()?);
+    let command = IpcCommand::connect_command(
+        process_name.map(str::to_string),
+        current_dir()?,
+        terminal_size(),
+    );
     send_command(&command)?;
     Ok(())
 }
@@ -30,6 +42,69 @@ pub fn stop_app(app_name: Option<&str>) -> EmptyResult {
     Ok(())
 }
 
+/// Send a captured request to the app again
+pub fn replay_request(id: u64, app_name: Option<&str>) -> EmptyResult {
+    let command = IpcCommand::replay_command(app_name.map(str::to_string), current_dir()?, id);
+    send_command(&command)?;
+    Ok(())
+}
+
+/// Turn an app's fault rules on or off, or just the named rule
+pub fn toggle_faults(enabled: bool, rule: Option<&str>, app_name: Option<&str>) -> EmptyResult {
+    let command = IpcCommand::faults_command(
+        app_name.map(str::to_string),
+        current_dir()?,
+        rule.map(str::to_string),
+        enabled,
+    );
+    send_command(&command)?;
+    Ok(())
+}
+
+/// Send a signal, like "USR2", to a process of the app in the current directory
+pub fn signal_process(signal: &str, process_name: Option<&str>) -> EmptyResult {
+    let command = IpcCommand::signal_command(
+        process_name.map(str::to_string),
+        current_dir()?,
+        signal.to_string(),
+    );
+    send_command(&command)?;
+    Ok(())
+}
+
+/// Print how recent runs of the app's processes ended
+pub fn process_history(process_name: Option<&str>, app_name: Option<&str>) -> EmptyResult {
+    let command = IpcCommand::history_command(
+        app_name.map(str::to_string),
+        current_dir()?,
+        process_name.map(str::to_string),
+    );
+    send_command(&command)?;
+    Ok(())
+}
+
+/// Print the location of the local CA certificate, optionally copying it elsewhere
+///
+/// The CA lives in the `config_dir` of the server's config file, if one is given.
+pub fn ca_certificate(config_file: Option<&str>, export_path: Option<&str>) -> EmptyResult {
+    let config_dir = match config_file {
+        Some(config_file) => config::read_config(config_file).general.config_dir,
+        None => config::config_dir(),
+    };
+    let cert_path = tls::existing_ca(&config_dir)?;
+
+    match export_path {
+        Some(export_path) => {
+            std::fs::copy(&cert_path, export_path)
+                .with_context(|| format!("Failed to export CA certificate to {}", export_path))?;
+            println!("{}", export_path);
+        }
+        None => println!("{}", cert_path.display()),
+    }
+
+    Ok(())
+}
+
 fn send_command(command: &IpcCommand) -> EmptyResult {
     let mut socket = UnixStream::connect(config::socket_path())
         .context("Failed to open socket. Is the server running?")?;
@@ -42,8 +117,12 @@ fn send_command(command: &IpcCommand) -> EmptyResult {
     serde_json::to_writer(&socket, &command)?;
     socket.write_all(b"\n")?;
     socket.flush()?;
+
+    let mut reader = BufReader::new(socket);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
     let response: IpcResponse =
-        serde_json::from_reader(socket).context("Failed to parse response from server")?;
+        serde_json::from_str(&line).context("Failed to parse response from server")?;
 
     match response {
         IpcResponse::ConnectionDetails {
@@ -54,14 +133,18 @@ fn send_command(command: &IpcCommand) -> EmptyResult {
             println!("Connecting tmux");
             let status = Command::new("tmux")
                 .env_remove("TMUX")
-                .args(&["-L", &tmux_socket])
-                .args(&["attach-session", "-t", &tmux_session])
+                .args(["-L", &tmux_socket])
+                .args(["attach-session", "-t", &tmux_session])
                 .status()?;
 
             if !status.success() {
                 bail!("Tmux reported Error");
             }
         }
+        IpcResponse::Terminal { process_name } => {
+            eprintln!("Connected to {}, press C-x to disconnect", process_name);
+            relay_terminal(reader)?;
+        }
         IpcResponse::Status(message) => println!("{}", message),
         IpcResponse::NotFound(message) => eprintln!("Server returned error: {}", message),
     }
@@ -69,6 +152,104 @@ fn send_command(command: &IpcCommand) -> EmptyResult {
     Ok(())
 }
 
+/// Pass the process's terminal through to ours until it exits or we disconnect
+fn relay_terminal(mut reader: BufReader<UnixStream>) -> EmptyResult {
+    let mut stdout = std::io::stdout();
+    stdout.write_all(reader.buffer())?;
+    stdout.flush()?;
+    reader.consume(reader.buffer().len());
+
+    let mut socket = reader.into_inner();
+    socket.set_read_timeout(None)?;
+    socket.set_write_timeout(None)?;
+
+    let stdin = std::io::stdin().as_raw_fd();
+    let _raw_mode = RawMode::enable(stdin);
+    let mut buffer = [0; 4096];
+
+    loop {
+        let mut fds = [
+            PollFd::new(stdin, PollFlags::POLLIN),
+            PollFd::new(socket.as_raw_fd(), PollFlags::POLLIN),
+        ];
+        poll(&mut fds, -1)?;
+
+        if fds[1].revents().is_some_and(|events| !events.is_empty()) {
+            let read = socket.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            stdout.write_all(&buffer[..read])?;
+            stdout.flush()?;
+        }
+
+        if fds[0].revents().is_some_and(|events| !events.is_empty()) {
+            // Read stdin directly, as its buffer would hide input from poll
+            let read = unistd::read(stdin, &mut buffer)?;
+            let input = &buffer[..read];
+
+            match input.iter().position(|&byte| byte == DETACH_KEY) {
+                Some(detach) => {
+                    socket.write_all(&input[..detach])?;
+                    break;
+                }
+                None if read == 0 => break,
+                None => socket.write_all(input)?,
+            }
+        }
+    }
+
+    eprint!("\r\nDisconnected\r\n");
+
+    Ok(())
+}
+
+/// Puts the terminal in raw mode so keys go straight to the process, until dropped
+struct RawMode {
+    fd: i32,
+    original: Option<Termios>,
+}
+
+impl RawMode {
+    fn enable(fd: i32) -> Self {
+        let original = termios::tcgetattr(fd).ok();
+
+        if let Some(original) = &original {
+            let mut raw = original.clone();
+            termios::cfmakeraw(&mut raw);
+            termios::tcsetattr(fd, SetArg::TCSANOW, &raw).ok();
+        }
+
+        Self { fd, original }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Some(original) = &self.original {
+            termios::tcsetattr(self.fd, SetArg::TCSANOW, original).ok();
+        }
+    }
+}
+
+/// Rows and columns of the terminal we're running in
+fn terminal_size() -> Option<(u16, u16)> {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+
+    let result = unsafe { libc::ioctl(std::io::stdout().as_raw_fd(), libc::TIOCGWINSZ, &mut size) };
+
+    if result == 0 && size.ws_row > 0 {
+        Some((size.ws_row, size.ws_col))
+    } else {
+        None
+    }
+}
+
 fn current_dir() -> ClientResult<String> {
     let current_dir_path = env::current_dir()?;
 
@@ -1,18 +1,26 @@
 use eyre::Context;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fs::{create_dir, File};
 use std::io::prelude::*;
 use std::path::PathBuf;
+use std::time::Duration;
 use tokio::fs::{read_dir as async_read_dir, File as AsyncFile};
 use tokio::io::AsyncReadExt;
 
 use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use nix::sys::signal::Signal;
 use serde::{
     de::{self, Unexpected},
     Deserialize, Deserializer,
 };
+use url::Url;
 
+use crate::alias::{self, Alias};
+use crate::faults::FaultRule;
+use crate::header_rules::HeaderRules;
 use crate::procfile;
+use crate::tmux;
 
 #[derive(Deserialize, Debug, Clone, Default)]
 pub struct Config {
@@ -20,17 +28,21 @@ pub struct Config {
 }
 
 impl Config {
+    /// Directory app configs are read from
+    pub(crate) fn app_config_dir(&self) -> PathBuf {
+        self.general.config_dir.join("apps")
+    }
+
     /// Read app configs from disk and return them as a Vec
     pub(crate) async fn app_configs(&self) -> Vec<App> {
-        let app_config_dir = self.general.config_dir.join("apps");
         let mut results = Vec::new();
-        match async_read_dir(app_config_dir).await {
+        match async_read_dir(self.app_config_dir()).await {
             Ok(mut entries) => {
                 while let Some(entry) = entries.next_entry().await.transpose() {
                     match read_app_config(entry).await {
                         Ok(app) => results.push(app),
                         Err(e) => {
-                            eprintln!("Skipping app config due to error: {}", e);
+                            eprintln!("Skipping app config due to error: {:#}", e);
                         }
                     }
                 }
@@ -43,6 +55,24 @@ impl Config {
 
         results
     }
+
+    /// Global TLDs followed by any others that the given apps use instead
+    pub(crate) fn domains_for(&self, apps: &[App]) -> Vec<String> {
+        let mut domains = self.general.domains.clone();
+
+        for tld in apps.iter().flat_map(|app| app.tlds.iter().flatten()) {
+            if !domains.contains(tld) {
+                domains.push(tld.clone());
+            }
+        }
+
+        domains
+    }
+
+    /// Aliases of different apps that match the same hosts, as warnings
+    pub(crate) async fn alias_conflicts(&self) -> Vec<String> {
+        alias::conflicts(&self.app_configs().await, &self.general.domains)
+    }
 }
 
 async fn read_app_config(entry: tokio::io::Result<tokio::fs::DirEntry>) -> color_eyre::Result<App> {
@@ -63,25 +93,99 @@ fn default_dns_port() -> u16 {
     6153
 }
 
-fn default_domain() -> String {
-    "test".to_string()
+fn default_domains() -> Vec<String> {
+    vec!["test".to_string()]
 }
 
 fn default_idle_timeout_secs() -> u64 {
     3600
 }
 
+fn default_upstream_pool_size() -> usize {
+    32
+}
+
+fn default_upstream_idle_timeout_secs() -> u64 {
+    90
+}
+
+fn default_upstream_connect_timeout_secs() -> u64 {
+    10
+}
+
+fn default_path_routing() -> bool {
+    true
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct ProxyConfig {
     pub proxy_port: u16,
     #[serde(default = "default_dns_port")]
     pub dns_port: u16,
-    #[serde(default = "default_domain")]
-    pub domain: String,
+    /// TLDs apps are served under, the first one is used for links
+    #[serde(
+        rename = "domain",
+        alias = "domains",
+        default = "default_domains",
+        deserialize_with = "parse_domains"
+    )]
+    pub domains: Vec<String>,
     #[serde(default = "config_dir")]
     pub config_dir: PathBuf,
     #[serde(default = "default_idle_timeout_secs")]
     pub idle_timeout_secs: u64,
+    /// Port for the HTTPS listener, HTTPS is disabled if this isn't set
+    pub https_port: Option<u16>,
+    /// Idle connections kept open to each app
+    #[serde(default = "default_upstream_pool_size")]
+    pub upstream_pool_size: usize,
+    /// How long idle connections to apps are kept open
+    #[serde(default = "default_upstream_idle_timeout_secs")]
+    pub upstream_idle_timeout_secs: u64,
+    /// How long to wait when connecting to an app
+    #[serde(default = "default_upstream_connect_timeout_secs")]
+    pub upstream_connect_timeout_secs: u64,
+    /// Route requests for other hosts, like localhost, by app name in the path
+    #[serde(default = "default_path_routing")]
+    pub path_routing: bool,
+    /// Path that app names follow when routing by path, e.g. "/apps" for "/apps/<app>/"
+    #[serde(default)]
+    pub path_routing_prefix: String,
+    /// What to do with requests for other hosts when used as a forward proxy
+    #[serde(default)]
+    pub forward_proxy: ForwardProxy,
+    /// How app processes are run
+    #[serde(default)]
+    pub process_backend: ProcessBackend,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardProxy {
+    /// Send them on to the internet
+    #[default]
+    Passthrough,
+    /// Only proxy requests for apps
+    Refuse,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProcessBackend {
+    /// In tmux sessions, which `connect` attaches to
+    Tmux,
+    /// On a pseudo-terminal owned by the server, which `connect` relays
+    Native,
+}
+
+impl Default for ProcessBackend {
+    fn default() -> Self {
+        if tmux::is_installed() {
+            Self::Tmux
+        } else {
+            Self::Native
+        }
+    }
 }
 
 impl Default for ProxyConfig {
@@ -89,26 +193,34 @@ impl Default for ProxyConfig {
         Self {
             proxy_port: 0,
             dns_port: default_dns_port(),
-            domain: default_domain(),
+            domains: default_domains(),
             config_dir: config_dir(),
             idle_timeout_secs: default_idle_timeout_secs(),
+            https_port: None,
+            upstream_pool_size: default_upstream_pool_size(),
+            upstream_idle_timeout_secs: default_upstream_idle_timeout_secs(),
+            upstream_connect_timeout_secs: default_upstream_connect_timeout_secs(),
+            path_routing: default_path_routing(),
+            path_routing_prefix: String::new(),
+            forward_proxy: ForwardProxy::default(),
+            process_backend: ProcessBackend::default(),
         }
     }
 }
 
-#[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq, Default)]
 #[serde(rename_all = "camelCase")]
 pub enum CommandConfig {
     Command(String),
     Commands(HashMap<String, String>),
     #[serde(deserialize_with = "true_to_unit")]
+    #[default]
     Procfile,
-}
-
-impl Default for CommandConfig {
-    fn default() -> Self {
-        CommandConfig::Procfile
-    }
+    /// Serve files from the app directory without running any processes
+    #[serde(deserialize_with = "true_to_unit")]
+    Static,
+    /// Existing server that isn't managed by oxidux, as a URL or "host:port"
+    Upstream(#[serde(deserialize_with = "parse_upstream")] Url),
 }
 
 impl CommandConfig {
@@ -120,6 +232,7 @@ impl CommandConfig {
                 .collect(),
             CommandConfig::Commands(map) => map.clone(),
             CommandConfig::Procfile => procfile::parse_procfile_in_dir(&directory),
+            CommandConfig::Upstream(_) | CommandConfig::Static => HashMap::new(),
         }
     }
 }
@@ -130,16 +243,294 @@ pub struct App {
     pub name: String,
     pub directory: String,
     pub port: Option<u16>,
-    #[serde(default)]
+    /// Headers set on requests to the app, superseded by `request_headers`
+    #[serde(default, deserialize_with = "parse_headers")]
     pub headers: HashMap<String, String>,
+    /// Rules for rewriting request headers before they're sent to the app
+    #[serde(default)]
+    pub request_headers: HeaderRules,
+    /// Rules for rewriting response headers before they're sent to the browser
+    #[serde(default)]
+    pub response_headers: HeaderRules,
     #[serde(flatten)]
     pub command_config: CommandConfig,
+    /// Other names for the app, which can be wildcard or regex patterns
     #[serde(default)]
-    pub aliases: Vec<String>,
+    pub aliases: Vec<Alias>,
+    /// TLDs for this app instead of the global ones
+    #[serde(
+        default,
+        rename = "domain",
+        alias = "domains",
+        deserialize_with = "parse_optional_domains"
+    )]
+    pub tlds: Option<Vec<String>>,
+    /// Give each process its own port, reachable at "<process>.<app>.<tld>"
+    #[serde(default)]
+    pub per_process_ports: bool,
+    /// Serve the root index file for missing paths in static apps
+    #[serde(default)]
+    pub spa_fallback: bool,
+    /// Skip certificate verification for HTTPS upstreams
+    #[serde(default)]
+    pub insecure_upstream: bool,
+    /// Unix socket path for processes to bind instead of a port
+    ///
+    /// Supports `{app}`, `{process}`, `{directory}` and `{config_dir}` placeholders.
+    pub socket: Option<String>,
+    /// Path prefixes served by another process or app
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    /// Hold requests until the app is listening instead of showing the autostart page
+    #[serde(default)]
+    pub wait_for_start: bool,
+    /// How long to hold requests while waiting for the app to start
+    pub start_timeout_secs: Option<u64>,
+    /// How long to wait for the app to respond before giving up with a 504
+    pub response_timeout_secs: Option<u64>,
+    /// Protocol spoken by the app's processes
+    #[serde(default)]
+    pub upstream_protocol: UpstreamProtocol,
+    /// Directory scripts and static files are served from, relative to the app directory
+    pub document_root: Option<String>,
+    /// Headers describing the original request, added to proxied requests
+    #[serde(default)]
+    pub forwarded_headers: ForwardedHeaders,
+    /// Whether the app sees the original Host header, defaults to rewrite for upstream apps only
+    pub host_header: Option<HostHeader>,
+    /// Rewriting of backend URLs in redirects and cookies
+    #[serde(default)]
+    pub rewrite_urls: RewriteUrls,
+    /// Recording of requests for the inspector
+    #[serde(default)]
+    pub capture: CaptureConfig,
+    /// Simulated latency and failures
+    #[serde(default)]
+    pub faults: Vec<FaultRule>,
+    /// Starting processes again after they exit on their own
+    #[serde(default)]
+    pub restart: RestartConfig,
+    /// Signals that stop processes
+    #[serde(default)]
+    pub stop: StopConfig,
+}
+
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    pub enabled: bool,
+    /// Number of recent requests to keep
+    pub max_requests: usize,
+    /// How much of each request and response body to keep
+    pub max_body_bytes: usize,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_requests: 100,
+            max_body_bytes: 64 * 1024,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RestartConfig {
+    /// Policy for processes not listed in `processes`
+    pub policy: RestartPolicy,
+    /// Policies for individual processes, by name
+    pub processes: HashMap<String, RestartPolicy>,
+    /// Restarts allowed within `window_secs` before a process is considered to be crash looping
+    pub max_retries: u32,
+    pub window_secs: u64,
+}
+
+impl Default for RestartConfig {
+    fn default() -> Self {
+        Self {
+            policy: RestartPolicy::default(),
+            processes: HashMap::new(),
+            max_retries: 5,
+            window_secs: 60,
+        }
+    }
+}
+
+impl RestartConfig {
+    pub fn policy_for(&self, process_name: &str) -> RestartPolicy {
+        self.processes
+            .get(process_name)
+            .copied()
+            .unwrap_or(self.policy)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Leave the process stopped until the app is requested or restarted
+    #[default]
+    Never,
+    /// Restart the process if it exits with an error or is killed
+    OnFailure,
+    /// Restart the process however it exits
+    Always,
+}
+
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StopConfig {
+    /// Signals sent in turn until the process exits
+    pub signals: Vec<StopSignal>,
+    /// How long to wait after each signal before sending the next
+    pub timeout_secs: u64,
+    /// Send the later signals when stopping the app too, not just when restarting it
+    pub escalate_on_stop: bool,
+    /// Overrides for individual processes, by name
+    pub processes: HashMap<String, ProcessStopConfig>,
+}
+
+impl Default for StopConfig {
+    fn default() -> Self {
+        Self {
+            signals: vec![StopSignal(Signal::SIGINT), StopSignal(Signal::SIGKILL)],
+            timeout_secs: 20,
+            escalate_on_stop: false,
+            processes: HashMap::new(),
+        }
+    }
+}
+
+impl StopConfig {
+    /// Signals and timeout for stopping the named process
+    pub fn sequence_for(&self, process_name: &str) -> (Vec<Signal>, Duration) {
+        let process = self.processes.get(process_name);
+        let signals = process
+            .and_then(|process| process.signals.as_ref())
+            .unwrap_or(&self.signals);
+        // Without any signals the process could never be stopped
+        let signals = match &signals[..] {
+            [] => StopConfig::default().signals,
+            signals => signals.to_vec(),
+        };
+        let timeout_secs = process
+            .and_then(|process| process.timeout_secs)
+            .unwrap_or(self.timeout_secs);
+
+        (
+            signals.iter().map(|signal| signal.0).collect(),
+            Duration::from_secs(timeout_secs),
+        )
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ProcessStopConfig {
+    pub signals: Option<Vec<StopSignal>>,
+    pub timeout_secs: Option<u64>,
+}
+
+/// Signal named with or without the "SIG" prefix, e.g. "TERM" or "SIGUSR2"
+#[derive(Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(try_from = "String")]
+pub struct StopSignal(pub Signal);
+
+impl TryFrom<String> for StopSignal {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        let name = name.trim().to_uppercase();
+        let name = if name.starts_with("SIG") {
+            name
+        } else {
+            format!("SIG{}", name)
+        };
+
+        name.parse()
+            .map(StopSignal)
+            .map_err(|_| format!("Unknown signal {}", name))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RewriteUrls {
+    /// Rewrite `Location`, `Content-Location` and `Refresh` headers pointing at the backend
+    pub redirects: bool,
+    /// Rewrite `Set-Cookie` domains naming the backend
+    pub cookies: bool,
+    /// Other hosts the app uses to refer to itself, as "host" or "host:port"
+    pub hosts: Vec<String>,
+}
+
+impl Default for RewriteUrls {
+    fn default() -> Self {
+        Self {
+            redirects: true,
+            cookies: true,
+            hosts: vec![],
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ForwardedHeaders {
+    /// Add `X-Forwarded-*` and `Forwarded` headers
+    pub enabled: bool,
+    /// Add the RFC 7239 `Forwarded` header along with the `X-Forwarded-*` ones
+    pub forwarded: bool,
+    /// Alternate names for the standard headers, e.g. `{ "X-Forwarded-For" = "X-Client-IP" }`
+    #[serde(deserialize_with = "parse_header_renames")]
+    pub rename: HashMap<String, HeaderName>,
+}
+
+impl Default for ForwardedHeaders {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            forwarded: true,
+            rename: HashMap::new(),
+        }
+    }
+}
+
+impl ForwardedHeaders {
+    /// Name to send a standard forwarding header as
+    pub fn header_name(&self, standard: &'static str) -> HeaderName {
+        self.rename
+            .get(standard)
+            .cloned()
+            .unwrap_or_else(|| HeaderName::from_static(standard))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HostHeader {
+    /// Pass the Host header through as the browser sent it
+    Preserve,
+    /// Replace the Host header with the backend's address, e.g. "localhost:7500"
+    Rewrite,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamProtocol {
+    #[default]
+    Http,
+    /// FastCGI responder, such as php-fpm
+    Fastcgi,
+    /// HTTP/2 without TLS, for gRPC servers
+    H2c,
 }
 
 impl App {
     pub fn parsed_headers(&self) -> HeaderMap {
+        // Names and values are checked when the config is loaded
         self.headers
             .iter()
             .map(|(key, value)| {
@@ -162,8 +553,68 @@ impl App {
         self.command_config.commands(self.full_path())
     }
 
-    pub(crate) fn domains(&self) -> impl Iterator<Item = &String> {
-        std::iter::once(&self.name).chain(self.aliases.iter())
+    /// Full path of the document root, defaulting to the app directory
+    pub fn full_document_root(&self) -> PathBuf {
+        let directory = PathBuf::from(self.full_path());
+
+        match &self.document_root {
+            Some(root) => directory.join(root),
+            None => directory,
+        }
+    }
+
+    /// The app's own TLDs, or `default` if it doesn't set any
+    pub(crate) fn tlds_or<'a>(&'a self, default: &'a [String]) -> &'a [String] {
+        self.tlds.as_deref().unwrap_or(default)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct Route {
+    /// Path prefix this route applies to, matched on whole path segments
+    pub prefix: String,
+    #[serde(flatten)]
+    pub target: RouteTarget,
+    /// Remove the prefix from the path before forwarding the request
+    #[serde(default)]
+    pub strip_prefix: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum RouteTarget {
+    /// Name of a process in the same app
+    Process(String),
+    /// Name of another configured app
+    App(String),
+}
+
+impl Route {
+    pub fn matches(&self, path: &str) -> bool {
+        let prefix = self.prefix.trim_end_matches('/');
+
+        match path.strip_prefix(prefix) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        }
+    }
+
+    /// Apply prefix stripping to a request path and query string
+    pub fn rewrite_path(&self, path_and_query: &str) -> String {
+        if !self.strip_prefix {
+            return path_and_query.to_string();
+        }
+
+        let prefix = self.prefix.trim_end_matches('/');
+        let rest = path_and_query
+            .strip_prefix(prefix)
+            .unwrap_or(path_and_query);
+
+        if rest.starts_with('/') {
+            rest.to_string()
+        } else {
+            format!("/{}", rest)
+        }
     }
 }
 
@@ -183,9 +634,10 @@ pub fn config_dir() -> PathBuf {
     let dir = home_dir.join(".oxidux");
 
     if !dir.is_dir() {
-        let result = create_dir(&dir);
-        if result.is_err() && !dir.is_dir() {
-            result.expect("Error creating config directory");
+        if let Err(e) = create_dir(&dir) {
+            if !dir.is_dir() {
+                panic!("Error creating config directory: {}", e);
+            }
         }
     }
 
@@ -201,6 +653,118 @@ pub fn tmux_socket() -> String {
     "oxidux".to_string()
 }
 
+fn parse_upstream<'a, D>(deserializer: D) -> Result<Url, D::Error>
+where
+    D: Deserializer<'a>,
+{
+    let upstream = String::deserialize(deserializer)?;
+
+    let url = if upstream.contains("://") {
+        Url::parse(&upstream)
+    } else {
+        Url::parse(&format!("http://{}", upstream))
+    };
+
+    url.map_err(|_| de::Error::invalid_value(Unexpected::Str(&upstream), &"a URL or host:port"))
+}
+
+/// Headers that can be renamed in `forwarded_headers.rename`
+const FORWARDING_HEADERS: &[&str] = &[
+    "x-forwarded-for",
+    "x-forwarded-host",
+    "x-forwarded-proto",
+    "x-forwarded-port",
+    "x-forwarded-subdomain",
+    "forwarded",
+];
+
+fn parse_header_renames<'a, D>(deserializer: D) -> Result<HashMap<String, HeaderName>, D::Error>
+where
+    D: Deserializer<'a>,
+{
+    HashMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(standard, name)| {
+            let standard = standard.to_lowercase();
+            if !FORWARDING_HEADERS.contains(&standard.as_str()) {
+                return Err(de::Error::invalid_value(
+                    Unexpected::Str(&standard),
+                    &"one of X-Forwarded-For, X-Forwarded-Host, X-Forwarded-Proto, X-Forwarded-Port, X-Forwarded-Subdomain or Forwarded",
+                ));
+            }
+
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| {
+                de::Error::invalid_value(Unexpected::Str(&name), &"a valid header name")
+            })?;
+
+            Ok((standard, name))
+        })
+        .collect()
+}
+
+/// Domains can be written as a single string or a list
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+fn parse_domains<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let domains = match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(domain) => vec![domain],
+        OneOrMany::Many(domains) => domains,
+    };
+
+    if domains.is_empty() {
+        return Err(de::Error::invalid_length(0, &"at least one domain"));
+    }
+
+    domains
+        .into_iter()
+        .map(|domain| {
+            let normalized = domain.trim_matches('.').to_ascii_lowercase();
+
+            if normalized.is_empty() || normalized.contains(|c: char| c.is_whitespace() || c == ':')
+            {
+                Err(de::Error::invalid_value(
+                    Unexpected::Str(&domain),
+                    &"a domain such as \"test\"",
+                ))
+            } else {
+                Ok(normalized)
+            }
+        })
+        .collect()
+}
+
+fn parse_optional_domains<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    parse_domains(deserializer).map(Some)
+}
+
+fn parse_headers<'a, D>(deserializer: D) -> Result<HashMap<String, String>, D::Error>
+where
+    D: Deserializer<'a>,
+{
+    let headers = HashMap::<String, String>::deserialize(deserializer)?;
+
+    for (name, value) in &headers {
+        HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| de::Error::invalid_value(Unexpected::Str(name), &"a valid header name"))?;
+        HeaderValue::from_str(value).map_err(|_| {
+            de::Error::invalid_value(Unexpected::Str(value), &"a valid header value")
+        })?;
+    }
+
+    Ok(headers)
+}
+
 fn true_to_unit<'a, D>(deserializer: D) -> Result<(), D::Error>
 where
     D: Deserializer<'a>,
@@ -232,13 +796,289 @@ mod tests {
         assert!(app.parsed_headers().contains_key(HOST));
     }
 
+    #[test]
+    fn test_invalid_header_deserialization() {
+        let data = "
+            directory = '/home/jon'
+            name = 'bar'
+            command = 'echo hello'
+            headers = {'bad header' = 'test'}
+        ";
+
+        assert!(toml::from_str::<App>(data).is_err());
+    }
+
+    #[test]
+    fn test_header_rules_deserialization() {
+        let data = "
+            directory = '/home/jon'
+            name = 'bar'
+            command = 'echo hello'
+
+            [request_headers]
+            set = { 'X-Forwarded-User' = 'dev@{app}.test' }
+
+            [response_headers]
+            remove = ['Strict-Transport-Security']
+        ";
+
+        let app: App = toml::from_str(data).unwrap();
+
+        assert!(!app.request_headers.is_empty());
+        assert!(!app.response_headers.is_empty());
+    }
+
+    #[test]
+    fn test_domain_deserialization() {
+        let config: Config = toml::from_str("[general]\nproxy_port = 80").unwrap();
+        assert_eq!(vec!["test"], config.general.domains);
+
+        let config: Config =
+            toml::from_str("[general]\nproxy_port = 80\ndomain = ['test', '.Localhost']").unwrap();
+        assert_eq!(vec!["test", "localhost"], config.general.domains);
+
+        let data = "
+            directory = '/home/jon'
+            name = 'bar'
+            command = 'echo hello'
+            domain = 'acme'
+        ";
+        let app: App = toml::from_str(data).unwrap();
+        assert_eq!(vec!["acme"], app.tlds_or(&config.general.domains));
+
+        assert!(toml::from_str::<Config>("[general]\nproxy_port = 80\ndomain = []").is_err());
+        assert!(toml::from_str::<Config>("[general]\nproxy_port = 80\ndomain = 'a b'").is_err());
+    }
+
+    #[test]
+    fn test_route_deserialization() {
+        let data = "
+            directory = '/home/jon'
+            name = 'bar'
+            command = 'echo hello'
+
+            [[routes]]
+            prefix = '/api'
+            process = 'api'
+            strip_prefix = true
+
+            [[routes]]
+            prefix = '/docs/'
+            app = 'docs'
+        ";
+
+        let app: App = toml::from_str(data).unwrap();
+
+        assert_eq!(
+            RouteTarget::Process("api".to_string()),
+            app.routes[0].target
+        );
+        assert!(app.routes[0].strip_prefix);
+        assert_eq!(RouteTarget::App("docs".to_string()), app.routes[1].target);
+        assert!(!app.routes[1].strip_prefix);
+    }
+
+    #[test]
+    fn test_upstream_deserialization() {
+        let data = "
+            directory = '~'
+            name = 'staging'
+            upstream = '192.168.56.10:3000'
+        ";
+
+        let app: App = toml::from_str(data).unwrap();
+
+        assert_eq!(
+            CommandConfig::Upstream(Url::parse("http://192.168.56.10:3000").unwrap()),
+            app.command_config
+        );
+        assert!(app.commands().is_empty());
+
+        let data = "
+            directory = '~'
+            name = 'staging'
+            upstream = 'https://staging.example.com/'
+            insecure_upstream = true
+        ";
+
+        let app: App = toml::from_str(data).unwrap();
+
+        assert_eq!(
+            CommandConfig::Upstream(Url::parse("https://staging.example.com/").unwrap()),
+            app.command_config
+        );
+        assert!(app.insecure_upstream);
+    }
+
+    #[test]
+    fn test_static_deserialization() {
+        let data = "
+            directory = '~/docs/build'
+            name = 'docs'
+            static = true
+            spa_fallback = true
+        ";
+
+        let app: App = toml::from_str(data).unwrap();
+
+        assert_eq!(CommandConfig::Static, app.command_config);
+        assert!(app.spa_fallback);
+    }
+
+    #[test]
+    fn test_fastcgi_deserialization() {
+        let data = "
+            directory = '/srv/blog'
+            name = 'blog'
+            command = 'php-fpm -F'
+            upstream_protocol = 'fastcgi'
+            document_root = 'public'
+        ";
+
+        let app: App = toml::from_str(data).unwrap();
+
+        assert_eq!(UpstreamProtocol::Fastcgi, app.upstream_protocol);
+        assert_eq!(PathBuf::from("/srv/blog/public"), app.full_document_root());
+    }
+
+    #[test]
+    fn test_forwarded_headers_deserialization() {
+        let data = "
+            directory = '~'
+            name = 'bar'
+            command = 'echo hello'
+            host_header = 'rewrite'
+
+            [forwarded_headers]
+            forwarded = false
+            rename = { 'X-Forwarded-For' = 'X-Client-IP' }
+        ";
+
+        let app: App = toml::from_str(data).unwrap();
+
+        assert_eq!(Some(HostHeader::Rewrite), app.host_header);
+        assert!(app.forwarded_headers.enabled);
+        assert!(!app.forwarded_headers.forwarded);
+        assert_eq!(
+            "x-client-ip",
+            app.forwarded_headers.header_name("x-forwarded-for")
+        );
+        assert_eq!(
+            "x-forwarded-host",
+            app.forwarded_headers.header_name("x-forwarded-host")
+        );
+
+        let data = "
+            directory = '~'
+            name = 'bar'
+            command = 'echo hello'
+            forwarded_headers = { rename = { 'X-Real-IP' = 'X-Client-IP' } }
+        ";
+
+        assert!(toml::from_str::<App>(data).is_err());
+    }
+
+    #[test]
+    fn test_restart_deserialization() {
+        let app: App = toml::from_str(
+            "
+            name = 'myapp'
+            directory = '/'
+            command = 'server'
+            [restart]
+            policy = 'on-failure'
+            processes = { worker = 'always' }
+            max_retries = 3
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(RestartPolicy::OnFailure, app.restart.policy_for("web"));
+        assert_eq!(RestartPolicy::Always, app.restart.policy_for("worker"));
+        assert_eq!(3, app.restart.max_retries);
+        assert_eq!(60, app.restart.window_secs);
+
+        let app: App =
+            toml::from_str("name = 'myapp'\ndirectory = '/'\ncommand = 'server'").unwrap();
+        assert_eq!(RestartPolicy::Never, app.restart.policy_for("web"));
+    }
+
+    #[test]
+    fn test_stop_deserialization() {
+        let app: App = toml::from_str(
+            "
+            name = 'myapp'
+            directory = '/'
+            command = 'server'
+            [stop]
+            signals = ['term', 'SIGQUIT', 'KILL']
+            timeout_secs = 5
+            [stop.processes.worker]
+            signals = ['USR1']
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            (
+                vec![Signal::SIGTERM, Signal::SIGQUIT, Signal::SIGKILL],
+                Duration::from_secs(5)
+            ),
+            app.stop.sequence_for("web")
+        );
+        assert_eq!(
+            (vec![Signal::SIGUSR1], Duration::from_secs(5)),
+            app.stop.sequence_for("worker")
+        );
+        assert!(!app.stop.escalate_on_stop);
+
+        let app: App = toml::from_str(
+            "name = 'myapp'\ndirectory = '/'\ncommand = 'server'\n[stop]\nescalate_on_stop = true",
+        )
+        .unwrap();
+        assert!(app.stop.escalate_on_stop);
+
+        let app: App =
+            toml::from_str("name = 'myapp'\ndirectory = '/'\ncommand = 'server'").unwrap();
+        assert_eq!(
+            (
+                vec![Signal::SIGINT, Signal::SIGKILL],
+                Duration::from_secs(20)
+            ),
+            app.stop.sequence_for("web")
+        );
+
+        let result = toml::from_str::<App>(
+            "name = 'myapp'\ndirectory = '/'\ncommand = 'server'\n[stop]\nsignals = ['NOPE']",
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_route_matching() {
+        let route = Route {
+            prefix: "/api".to_string(),
+            target: RouteTarget::Process("api".to_string()),
+            strip_prefix: true,
+        };
+
+        assert!(route.matches("/api"));
+        assert!(route.matches("/api/users"));
+        assert!(!route.matches("/apiary"));
+        assert!(!route.matches("/"));
+
+        assert_eq!("/users?page=2", route.rewrite_path("/api/users?page=2"));
+        assert_eq!("/", route.rewrite_path("/api"));
+        assert_eq!("/?page=2", route.rewrite_path("/api?page=2"));
+    }
+
     #[tokio::test]
     async fn load_app_config() {
         let tmp = test_utils::temp_dir();
         let app_dir = tmp.join("apps");
         create_dir(&app_dir).unwrap();
 
-        let mut app_file = File::create(&app_dir.join("testapp.toml")).unwrap();
+        let mut app_file = File::create(app_dir.join("testapp.toml")).unwrap();
 
         app_file
             .write_all(
@@ -27,19 +27,21 @@ use crate::proxy::launchd;
 // This can probably be anything, it's built for DDOS prevention
 const TCP_TIMEOUT: u64 = 5;
 
-/// Start a local DNS server to point our development TLD to localhost
+/// Start a local DNS server to point our development TLDs to localhost
 ///
 /// This is intended for use only with the MacOS resolver system, it can't be used as a regular DNS
 /// server to do real lookups.
-pub fn start_dns_server(port: u16, domain: &str) -> color_eyre::Result<()> {
+pub fn start_dns_server(port: u16, domains: &[String]) -> color_eyre::Result<()> {
     let dns_address = format!("127.0.0.1:{}", port);
     let mut catalog = Catalog::new();
 
-    let name = Name::from_str(domain).unwrap();
-    let authority = LocalhostAuthority {
-        name: name.clone().into(),
-    };
-    catalog.upsert(name.into(), Box::new(Arc::new(RwLock::new(authority))));
+    for domain in domains {
+        let name = Name::from_str(domain)?;
+        let authority = LocalhostAuthority {
+            name: name.clone().into(),
+        };
+        catalog.upsert(name.into(), Box::new(Arc::new(RwLock::new(authority))));
+    }
 
     let mut server = ServerFuture::new(catalog);
     let address: SocketAddr = dns_address.parse().unwrap();
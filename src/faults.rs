@@ -0,0 +1,310 @@
+// Simulated slow and failing backends, for testing how clients cope
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use hyper::StatusCode;
+use serde::Deserialize;
+
+/// Fault rule as written in the app config
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct RawFaultRule {
+    name: Option<String>,
+    #[serde(default = "default_path")]
+    path: String,
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+    latency_ms: Option<u64>,
+    bandwidth_kbps: Option<u64>,
+    #[serde(default)]
+    error_rate: f64,
+    #[serde(default = "default_error_status")]
+    error_status: u16,
+    #[serde(default)]
+    reset_rate: f64,
+    #[serde(default)]
+    websocket_drop_rate: f64,
+}
+
+fn default_path() -> String {
+    "*".to_string()
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_error_status() -> u16 {
+    503
+}
+
+/// Faults applied to requests with a path matching `path`, where `*` matches anything
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "RawFaultRule")]
+pub struct FaultRule {
+    pub name: String,
+    pub path: String,
+    pub enabled: bool,
+    /// Delay before the request is sent to the app
+    pub latency: Option<Duration>,
+    /// Maximum rate response bodies are sent at
+    pub bytes_per_sec: Option<u64>,
+    /// Fraction of requests answered with `error_status` instead of reaching the app
+    pub error_rate: f64,
+    pub error_status: StatusCode,
+    /// Fraction of requests whose connection is closed without a response
+    pub reset_rate: f64,
+    /// Fraction of WebSocket messages that are silently discarded
+    pub websocket_drop_rate: f64,
+}
+
+impl TryFrom<RawFaultRule> for FaultRule {
+    type Error = String;
+
+    fn try_from(raw: RawFaultRule) -> Result<Self, Self::Error> {
+        let name = raw.name.clone().unwrap_or_else(|| raw.path.clone());
+
+        for (setting, rate) in &[
+            ("error_rate", raw.error_rate),
+            ("reset_rate", raw.reset_rate),
+            ("websocket_drop_rate", raw.websocket_drop_rate),
+        ] {
+            if !(0.0..=1.0).contains(rate) {
+                return Err(format!(
+                    "fault {:?}: {} must be between 0 and 1, got {}",
+                    name, setting, rate
+                ));
+            }
+        }
+
+        let error_status = StatusCode::from_u16(raw.error_status)
+            .ok()
+            .filter(StatusCode::is_server_error)
+            .ok_or_else(|| {
+                format!(
+                    "fault {:?}: error_status must be a 5xx status, got {}",
+                    name, raw.error_status
+                )
+            })?;
+
+        if raw.bandwidth_kbps == Some(0) {
+            return Err(format!("fault {:?}: bandwidth_kbps can't be 0", name));
+        }
+
+        Ok(Self {
+            name,
+            path: raw.path,
+            enabled: raw.enabled,
+            latency: raw.latency_ms.map(Duration::from_millis),
+            bytes_per_sec: raw.bandwidth_kbps.map(|kbps| kbps * 1000 / 8),
+            error_rate: raw.error_rate,
+            error_status,
+            reset_rate: raw.reset_rate,
+            websocket_drop_rate: raw.websocket_drop_rate,
+        })
+    }
+}
+
+// Rates are checked to be between 0 and 1 when parsed, so they're never NaN
+impl Eq for FaultRule {}
+
+impl FaultRule {
+    pub fn matches(&self, path: &str) -> bool {
+        glob_match(self.path.as_bytes(), path.as_bytes())
+    }
+}
+
+impl fmt::Display for FaultRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path)?;
+
+        if let Some(latency) = self.latency {
+            write!(f, ", {}ms latency", latency.as_millis())?;
+        }
+        if let Some(bytes_per_sec) = self.bytes_per_sec {
+            write!(f, ", {}kbps", bytes_per_sec * 8 / 1000)?;
+        }
+        if self.error_rate > 0.0 {
+            write!(
+                f,
+                ", {}% {}",
+                self.error_rate * 100.0,
+                self.error_status.as_u16()
+            )?;
+        }
+        if self.reset_rate > 0.0 {
+            write!(f, ", {}% resets", self.reset_rate * 100.0)?;
+        }
+        if self.websocket_drop_rate > 0.0 {
+            write!(
+                f,
+                ", {}% WebSocket messages dropped",
+                self.websocket_drop_rate * 100.0
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// An app's fault rules, which can be switched on and off while it's running
+#[derive(Debug)]
+pub struct Faults {
+    rules: Vec<(FaultRule, AtomicBool)>,
+}
+
+impl Faults {
+    pub fn new(rules: &[FaultRule]) -> Self {
+        let rules = rules
+            .iter()
+            .map(|rule| (rule.clone(), AtomicBool::new(rule.enabled)))
+            .collect();
+
+        Self { rules }
+    }
+
+    /// First enabled rule matching the path
+    pub fn find(&self, path: &str) -> Option<&FaultRule> {
+        self.rules
+            .iter()
+            .find(|(rule, enabled)| enabled.load(Ordering::Relaxed) && rule.matches(path))
+            .map(|(rule, _)| rule)
+    }
+
+    /// Turn the named rule, or all rules, on or off, returning the names of the rules changed
+    pub fn set_enabled(&self, name: Option<&str>, enabled: bool) -> Result<Vec<String>, String> {
+        let changed: Vec<String> = self
+            .rules
+            .iter()
+            .filter(|(rule, _)| name.is_none_or(|name| rule.name == name))
+            .map(|(rule, state)| {
+                state.store(enabled, Ordering::Relaxed);
+                rule.name.clone()
+            })
+            .collect();
+
+        match name {
+            Some(name) if changed.is_empty() => Err(format!("No fault rule named {:?}", name)),
+            _ => Ok(changed),
+        }
+    }
+
+    /// One line per rule, for the status page
+    pub fn status(&self) -> String {
+        self.rules
+            .iter()
+            .map(|(rule, enabled)| {
+                let state = if enabled.load(Ordering::Relaxed) {
+                    "on"
+                } else {
+                    "off"
+                };
+
+                format!("fault {} ({}): {}\n", rule.name, state, rule)
+            })
+            .collect()
+    }
+}
+
+/// Match `text` against a pattern where `*` matches any run of characters
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|skip| glob_match(rest, &text[skip..])),
+        Some((c, rest)) => text.first() == Some(c) && glob_match(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(source: &str) -> Vec<FaultRule> {
+        #[derive(Deserialize)]
+        struct Config {
+            faults: Vec<FaultRule>,
+        }
+
+        toml::from_str::<Config>(source).unwrap().faults
+    }
+
+    #[test]
+    fn matches_path_patterns() {
+        let rule = &rules("[[faults]]\npath = '/api/*/items'")[0];
+
+        assert!(rule.matches("/api/v1/items"));
+        assert!(rule.matches("/api/v1/nested/items"));
+        assert!(!rule.matches("/api/v1/items/1"));
+        assert!(!rule.matches("/other"));
+        assert!(rules("[[faults]]")[0].matches("/anything"));
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rule = &rules(
+            "
+            [[faults]]
+            name = 'slow-api'
+            path = '/api/*'
+            latency_ms = 250
+            bandwidth_kbps = 64
+            error_rate = 0.1
+            error_status = 502
+            ",
+        )[0];
+
+        assert_eq!("slow-api", rule.name);
+        assert_eq!(Some(Duration::from_millis(250)), rule.latency);
+        assert_eq!(Some(8000), rule.bytes_per_sec);
+        assert_eq!(StatusCode::BAD_GATEWAY, rule.error_status);
+        assert_eq!("/api/*, 250ms latency, 64kbps, 10% 502", rule.to_string());
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        #[derive(Deserialize, Debug)]
+        struct Config {
+            #[allow(dead_code)]
+            faults: Vec<FaultRule>,
+        }
+
+        let error = toml::from_str::<Config>("[[faults]]\nerror_rate = 5").unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("error_rate must be between 0 and 1"));
+
+        let error = toml::from_str::<Config>("[[faults]]\nerror_status = 404").unwrap_err();
+        assert!(error.to_string().contains("5xx"));
+    }
+
+    #[test]
+    fn toggles_rules() {
+        let faults = Faults::new(&rules(
+            "
+            [[faults]]
+            name = 'api'
+            path = '/api/*'
+            [[faults]]
+            name = 'everything'
+            enabled = false
+            ",
+        ));
+
+        assert_eq!("api", faults.find("/api/items").unwrap().name);
+        assert!(faults.find("/other").is_none());
+
+        faults.set_enabled(Some("api"), false).unwrap();
+        faults.set_enabled(Some("everything"), true).unwrap();
+        assert_eq!("everything", faults.find("/api/items").unwrap().name);
+
+        assert_eq!(
+            vec!["api", "everything"],
+            faults.set_enabled(None, false).unwrap()
+        );
+        assert!(faults.find("/api/items").is_none());
+        assert!(faults.set_enabled(Some("missing"), true).is_err());
+        assert!(faults.status().contains("fault api (off): /api/*"));
+    }
+}
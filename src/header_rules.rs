@@ -0,0 +1,327 @@
+// Configurable header rewriting for requests and responses
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::HeaderMap;
+use serde::Deserialize;
+
+/// Variables that can be used in header value templates
+const VARIABLES: &[&str] = &[
+    "app",
+    "process",
+    "port",
+    "client_addr",
+    "request_id",
+    "subdomain",
+];
+
+/// Values for template variables, describing the request being proxied
+#[derive(Debug, Default)]
+pub struct TemplateContext {
+    pub app: String,
+    pub process: String,
+    pub port: String,
+    pub client_addr: String,
+    pub request_id: String,
+    /// Part of the host in front of the app name, or matched by an alias pattern
+    pub subdomain: String,
+}
+
+impl TemplateContext {
+    fn get(&self, variable: &str) -> &str {
+        match variable {
+            "app" => &self.app,
+            "process" => &self.process,
+            "port" => &self.port,
+            "client_addr" => &self.client_addr,
+            "request_id" => &self.request_id,
+            "subdomain" => &self.subdomain,
+            _ => unreachable!("Template variables are checked when parsed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Variable(String),
+}
+
+/// Header value with `{variable}` placeholders, `{{` and `}}` are literal braces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = source.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut variable = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => variable.push(c),
+                            None => return Err(format!("unclosed {{{}", variable)),
+                        }
+                    }
+
+                    if !VARIABLES.contains(&variable.as_str()) {
+                        return Err(format!(
+                            "unknown variable {{{}}}, expected one of {}",
+                            variable,
+                            VARIABLES
+                                .iter()
+                                .map(|name| format!("{{{}}}", name))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ));
+                    }
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Variable(variable));
+                }
+                '}' => return Err("unmatched }, use }} for a literal brace".to_string()),
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        let template = Self { segments };
+        // Variables are filled with plain text, so checking the literal parts is enough
+        HeaderValue::from_str(&template.render(&TemplateContext::default()))
+            .map_err(|_| format!("invalid header value {:?}", source))?;
+
+        Ok(template)
+    }
+
+    pub fn render(&self, context: &TemplateContext) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(text) => text.as_str(),
+                Segment::Variable(name) => context.get(name),
+            })
+            .collect()
+    }
+}
+
+/// Header rules as written in the app config
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct RawHeaderRules {
+    #[serde(default)]
+    set: HashMap<String, String>,
+    #[serde(default)]
+    append: HashMap<String, String>,
+    #[serde(default)]
+    remove: Vec<String>,
+}
+
+/// Headers to remove, set and append, applied in that order
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(try_from = "RawHeaderRules")]
+pub struct HeaderRules {
+    remove: Vec<HeaderName>,
+    set: Vec<(HeaderName, Template)>,
+    append: Vec<(HeaderName, Template)>,
+}
+
+impl TryFrom<RawHeaderRules> for HeaderRules {
+    type Error = String;
+
+    fn try_from(raw: RawHeaderRules) -> Result<Self, Self::Error> {
+        let remove = raw
+            .remove
+            .iter()
+            .map(|name| parse_name(name))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            remove,
+            set: parse_templates(raw.set)?,
+            append: parse_templates(raw.append)?,
+        })
+    }
+}
+
+impl HeaderRules {
+    /// Rules that set each header to a fixed value, for the legacy `headers` setting
+    pub fn from_fixed(headers: &HeaderMap) -> Self {
+        let set = headers
+            .iter()
+            .map(|(name, value)| {
+                let text = String::from_utf8_lossy(value.as_bytes()).to_string();
+                let template = Template {
+                    segments: vec![Segment::Literal(text)],
+                };
+
+                (name.clone(), template)
+            })
+            .collect();
+
+        Self {
+            set,
+            ..Default::default()
+        }
+    }
+
+    /// Add the rules from `other`, so they're applied after these
+    pub fn merge(mut self, other: &HeaderRules) -> Self {
+        self.remove.extend(other.remove.iter().cloned());
+        self.set.extend(other.set.iter().cloned());
+        self.append.extend(other.append.iter().cloned());
+
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remove.is_empty() && self.set.is_empty() && self.append.is_empty()
+    }
+
+    pub fn apply(&self, headers: &mut HeaderMap, context: &TemplateContext) {
+        for name in &self.remove {
+            headers.remove(name);
+        }
+
+        for (name, template) in &self.set {
+            if let Some(value) = render_value(name, template, context) {
+                headers.insert(name.clone(), value);
+            }
+        }
+
+        for (name, template) in &self.append {
+            if let Some(value) = render_value(name, template, context) {
+                headers.append(name.clone(), value);
+            }
+        }
+    }
+}
+
+fn render_value(
+    name: &HeaderName,
+    template: &Template,
+    context: &TemplateContext,
+) -> Option<HeaderValue> {
+    let value = template.render(context);
+
+    match HeaderValue::from_str(&value) {
+        Ok(value) => Some(value),
+        Err(_) => {
+            eprintln!("Skipping header {} with invalid value {:?}", name, value);
+            None
+        }
+    }
+}
+
+fn parse_name(name: &str) -> Result<HeaderName, String> {
+    HeaderName::from_bytes(name.as_bytes()).map_err(|_| format!("invalid header name {:?}", name))
+}
+
+fn parse_templates(
+    headers: HashMap<String, String>,
+) -> Result<Vec<(HeaderName, Template)>, String> {
+    let mut templates = headers
+        .into_iter()
+        .map(|(name, value)| {
+            let template =
+                Template::parse(&value).map_err(|e| format!("header {:?}: {}", name, e))?;
+
+            Ok((parse_name(&name)?, template))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    // Keep the order stable since HashMap iteration order is random
+    templates.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+
+    Ok(templates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> TemplateContext {
+        TemplateContext {
+            app: "myapp".to_string(),
+            process: "web".to_string(),
+            port: "7500".to_string(),
+            client_addr: "127.0.0.1".to_string(),
+            request_id: "abc123".to_string(),
+            subdomain: "acme".to_string(),
+        }
+    }
+
+    #[test]
+    fn renders_templates() {
+        let template = Template::parse("{process}@{app}:{port} {{literal}}").unwrap();
+        assert_eq!("web@myapp:7500 {literal}", template.render(&context()));
+
+        let template = Template::parse("tenant-{subdomain}").unwrap();
+        assert_eq!("tenant-acme", template.render(&context()));
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        let error = Template::parse("{user}@example.com").unwrap_err();
+        assert!(error.contains("unknown variable {user}"), "{}", error);
+
+        assert!(Template::parse("a}b").is_err());
+        assert!(Template::parse("{app").is_err());
+        assert!(Template::parse("line\nbreak").is_err());
+    }
+
+    #[test]
+    fn applies_rules_in_order() {
+        let rules: HeaderRules = toml::from_str(
+            "
+            remove = ['Strict-Transport-Security', 'X-Old']
+            set = { 'X-Old' = 'replaced', 'X-Request-Id' = '{request_id}' }
+            append = { 'Vary' = 'Origin' }
+            ",
+        )
+        .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("strict-transport-security", "max-age=100".parse().unwrap());
+        headers.insert("x-old", "original".parse().unwrap());
+        headers.insert("vary", "Accept".parse().unwrap());
+
+        rules.apply(&mut headers, &context());
+
+        assert!(!headers.contains_key("strict-transport-security"));
+        assert_eq!("replaced", headers["x-old"]);
+        assert_eq!("abc123", headers["x-request-id"]);
+        assert_eq!(
+            vec!["Accept", "Origin"],
+            headers.get_all("vary").iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn reports_invalid_rules() {
+        let error = toml::from_str::<HeaderRules>("set = { 'Bad Name' = 'x' }").unwrap_err();
+        assert!(error.to_string().contains("invalid header name"));
+
+        let error = toml::from_str::<HeaderRules>("set = { 'X-User' = '{user}' }").unwrap_err();
+        assert!(error.to_string().contains("X-User"));
+    }
+}
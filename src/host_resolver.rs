@@ -1,60 +1,162 @@
+use std::cmp::Reverse;
+
+use crate::alias::{self, Alias, HostMatch, Precedence};
 use crate::app::App;
 use crate::process_manager::ProcessManager;
 
 /// Find the associated `App` for a given hostname
 ///
-/// Looks in running apps and then falls back to creating the app from config
+/// Looks in running apps and then falls back to creating the app from config. When several apps
+/// match, the best match wins, see `alias::Precedence`.
 pub(crate) async fn resolve(host: &str) -> Option<App> {
+    find_or_add(|name, aliases, tlds| {
+        split_host(host, name, aliases, tlds).map(|found| found.precedence)
+    })
+    .await
+}
+
+/// Find the `App` with the given name, setting it up from config if needed
+pub(crate) async fn resolve_name(name: &str) -> Option<App> {
+    find_or_add(|app_name, _, _| Some(Precedence::Exact).filter(|_| app_name == name)).await
+}
+
+async fn find_or_add(
+    matcher: impl Fn(&str, &[Alias], &[String]) -> Option<Precedence>,
+) -> Option<App> {
     let process_manager = ProcessManager::global_read().await;
 
-    for app in &process_manager.apps {
-        let domains = app.domains();
+    // Ties go to the app whose name sorts first
+    let running = process_manager
+        .apps
+        .iter()
+        .filter_map(|app| Some((matcher(app.name(), app.aliases(), app.tlds())?, app)))
+        .max_by_key(|&(precedence, app)| (precedence, Reverse(app.name())));
 
-        if matches_domains(host, domains) {
-            return Some(app.clone());
-        }
+    // Nothing can beat an exact match, so skip reading the configs
+    if let Some((Precedence::Exact, app)) = running {
+        return Some(app.clone());
     }
 
-    let mut app_config = None;
-
-    for config in process_manager.config().app_configs().await {
-        let domains = config.domains();
+    let default_tlds = &process_manager.config().general.domains;
+    let configs = process_manager.app_configs().await;
+    let added = configs
+        .iter()
+        .filter(|config| process_manager.find_app_by_name(&config.name).is_none())
+        .filter_map(|config| {
+            let tlds = config.tlds_or(default_tlds);
+            Some((matcher(&config.name, &config.aliases, tlds)?, config))
+        })
+        .max_by_key(|&(precedence, config)| (precedence, Reverse(config.name.as_str())));
 
-        if matches_domains(host, domains) {
-            app_config = Some(config.clone());
-            break;
+    let app_config = match (running, added) {
+        (Some((running, app)), Some((precedence, config)))
+            if (running, Reverse(app.name())) > (precedence, Reverse(config.name.as_str())) =>
+        {
+            return Some(app.clone())
         }
-    }
+        (Some((_, app)), None) => return Some(app.clone()),
+        (_, Some((_, config))) => config.clone(),
+        (None, None) => return None,
+    };
     drop(process_manager);
-    let app_config = app_config?;
 
     // Upgrade to a write lock
     let mut process_manager = ProcessManager::global_write().await;
     Some(process_manager.add_app(app_config))
 }
 
-/// Check if the provided hostname matches one of the app domains
-fn matches_domains<'a>(host: &str, mut domains: impl Iterator<Item = &'a String>) -> bool {
-    let parts = host.split('.');
-    // Penultimate segment should contain "domain", we don't currently allow specifying subdomains
-    let needle = parts.rev().nth(1).unwrap_or(host);
-    domains.any(|domain| domain == needle)
+/// Match the host against an app's name and aliases under each of its TLDs
+fn split_host<'a>(
+    host: &'a str,
+    app_name: &str,
+    aliases: &[Alias],
+    tlds: &[String],
+) -> Option<HostMatch<'a>> {
+    let host = strip_port(host);
+
+    tlds.iter()
+        .filter_map(|tld| {
+            strip_suffix_ignore_case(host, tld)
+                .and_then(|rest| rest.strip_suffix('.'))
+                .and_then(|name| alias::best_match(app_name, aliases, name))
+        })
+        .max_by_key(|found| found.precedence)
+}
+
+/// Labels in front of the app domain, e.g. "webpack" for "webpack.myapp.test"
+///
+/// For wildcard and regex aliases it's what the first wildcard or group matched.
+pub(crate) fn subdomain<'a>(host: &'a str, app: &App) -> Option<&'a str> {
+    split_host(host, app.name(), app.aliases(), app.tlds())?.subdomain
+}
+
+/// Labels in front of the app's name or an exact alias, which can name one of its processes
+///
+/// Unlike `subdomain`, what wildcard and regex aliases matched is left out, as that names
+/// something like a tenant rather than a process.
+pub(crate) fn process_subdomain<'a>(host: &'a str, app: &App) -> Option<&'a str> {
+    let found = split_host(host, app.name(), app.aliases(), app.tlds())?;
+
+    match found.precedence {
+        Precedence::Subdomain(_) => found.subdomain,
+        _ => None,
+    }
+}
+
+/// Check if the host is under one of the dev domains, such as "myapp.test"
+pub(crate) fn is_app_domain(host: &str, domains: &[String]) -> bool {
+    let host = strip_port(host);
+
+    domains.iter().any(|domain| {
+        strip_suffix_ignore_case(host, domain).is_some_and(|name| name.ends_with('.'))
+    })
+}
+
+fn strip_suffix_ignore_case<'a>(text: &'a str, suffix: &str) -> Option<&'a str> {
+    let split = text.len().checked_sub(suffix.len())?;
+
+    if text.is_char_boundary(split) && text[split..].eq_ignore_ascii_case(suffix) {
+        Some(&text[..split])
+    } else {
+        None
+    }
+}
+
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((hostname, port)) if port.chars().all(|c| c.is_ascii_digit()) => hostname,
+        _ => host,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config::{App, Config};
+    use std::convert::TryFrom;
+
+    fn aliases(sources: &[&str]) -> Vec<Alias> {
+        sources
+            .iter()
+            .map(|source| Alias::try_from(source.to_string()).unwrap())
+            .collect()
+    }
 
     #[tokio::test]
     async fn resolve_test() {
         ProcessManager::initialize(&Config::default());
         let app_config = App {
             name: "appname".to_string(),
-            aliases: vec!["appalias".to_string()],
+            aliases: aliases(&["appalias"]),
+            ..Default::default()
+        };
+        let tenants_config = App {
+            name: "tenants".to_string(),
+            aliases: aliases(&["*.appname"]),
             ..Default::default()
         };
         ProcessManager::global_write().await.add_app(app_config);
+        ProcessManager::global_write().await.add_app(tenants_config);
 
         let app = resolve("appname.test").await.unwrap();
         assert_eq!("appname", app.name());
@@ -64,5 +166,48 @@ mod tests {
 
         let subdomain_app = resolve("subdomain.appalias.test").await.unwrap();
         assert_eq!("appname", subdomain_app.name());
+        assert_eq!(
+            Some("subdomain"),
+            subdomain("subdomain.appalias.test", &subdomain_app)
+        );
+        assert!(resolve("appname.example.com").await.is_none());
+
+        // Wildcards beat subdomains of a name
+        let tenant_app = resolve("acme.appname.test").await.unwrap();
+        assert_eq!("tenants", tenant_app.name());
+        assert_eq!(Some("acme"), subdomain("acme.appname.test", &tenant_app));
+        assert_eq!("appname", resolve("a.b.appname.test").await.unwrap().name());
+
+        let named_app = resolve_name("appname").await.unwrap();
+        assert_eq!("appname", named_app.name());
+        assert!(resolve_name("appalias").await.is_none());
+    }
+
+    #[test]
+    fn split_host_test() {
+        let aliases = aliases(&["alias", "shop-*.example"]);
+        let tlds = vec!["test".to_string(), "dev.acme".to_string()];
+        let split =
+            |host| split_host(host, "appname", &aliases, &tlds).map(|found| found.subdomain);
+
+        assert_eq!(Some(None), split("appname.test"));
+        assert_eq!(Some(None), split("Alias.Dev.Acme:8080"));
+        assert_eq!(Some(Some("webpack")), split("webpack.appname.test"));
+        assert_eq!(Some(Some("a.webpack")), split("a.webpack.alias.dev.acme"));
+        assert_eq!(Some(Some("acme")), split("shop-acme.example.test"));
+        assert_eq!(None, split("appname.localhost"));
+        assert_eq!(None, split("otherappname.test"));
+        assert_eq!(None, split("appname.test.example.com"));
+    }
+
+    #[test]
+    fn app_domain_test() {
+        let domains = vec!["test".to_string(), "localhost".to_string()];
+
+        assert!(is_app_domain("appname.test", &domains));
+        assert!(is_app_domain("webpack.appname.localhost:8080", &domains));
+        assert!(!is_app_domain("localhost:8080", &domains));
+        assert!(!is_app_domain("test", &domains));
+        assert!(!is_app_domain("contest", &domains));
     }
 }
@@ -0,0 +1,607 @@
+// Opt-in capture of requests passing through the proxy, for debugging and replay
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use base64::Engine;
+use hyper::body::HttpBody;
+use hyper::header::{CONTENT_TYPE, HOST};
+use hyper::{Body, HeaderMap, Request, Response, StatusCode};
+use serde_json::{json, Value};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::config::CaptureConfig;
+
+/// Error that kept the app from responding, attached to the proxy's error response
+#[derive(Clone, Debug)]
+pub struct UpstreamError(pub String);
+
+/// Up to `max_body_bytes` of a request or response body
+#[derive(Clone, Debug, Default)]
+pub struct CapturedBody {
+    pub data: Vec<u8>,
+    /// Full size of the body, including anything past the capture limit
+    pub size: u64,
+    pub truncated: bool,
+    /// Set once the whole body has been seen
+    pub complete: bool,
+}
+
+impl CapturedBody {
+    fn record(&mut self, chunk: &[u8], limit: usize) {
+        self.size += chunk.len() as u64;
+
+        let room = limit.saturating_sub(self.data.len());
+        if chunk.len() > room {
+            self.truncated = true;
+        }
+        self.data.extend_from_slice(&chunk[..chunk.len().min(room)]);
+    }
+
+    fn text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.data).ok()
+    }
+}
+
+/// A request and the response it got
+#[derive(Clone, Debug)]
+pub struct Exchange {
+    pub id: u64,
+    pub started_at: SystemTime,
+    pub method: String,
+    /// Full URL as requested by the browser
+    pub url: String,
+    pub http_version: String,
+    pub request_headers: Vec<(String, String)>,
+    pub request_body: CapturedBody,
+    pub status: Option<StatusCode>,
+    pub response_headers: Vec<(String, String)>,
+    pub response_body: CapturedBody,
+    /// Time until the response headers were ready
+    pub wait: Option<Duration>,
+    /// Time until the response body was finished
+    pub total: Option<Duration>,
+    pub error: Option<String>,
+}
+
+impl Exchange {
+    fn summary_line(&self) -> String {
+        let status = self
+            .status
+            .map(|status| status.as_u16().to_string())
+            .unwrap_or_else(|| "---".to_string());
+        let time = self
+            .wait
+            .map(|wait| format!("{}ms", wait.as_millis()))
+            .unwrap_or_else(|| "pending".to_string());
+
+        format!(
+            "{:>5}  {}  {} {:>7}  {} {}\n",
+            self.id,
+            format_time(self.started_at),
+            status,
+            time,
+            self.method,
+            self.url
+        )
+    }
+
+    /// Plain text dump of the request and response
+    pub fn details(&self) -> String {
+        let mut output = String::new();
+
+        writeln!(
+            output,
+            "Request {} at {}",
+            self.id,
+            format_time(self.started_at)
+        )
+        .ok();
+        if let Some(error) = &self.error {
+            writeln!(output, "Upstream error: {}", error).ok();
+        }
+        if let Some(total) = self.total.or(self.wait) {
+            writeln!(output, "Time: {}ms", total.as_millis()).ok();
+        }
+
+        writeln!(
+            output,
+            "\n{} {} {}",
+            self.method, self.url, self.http_version
+        )
+        .ok();
+        write_headers(&mut output, &self.request_headers);
+        write_body(&mut output, &self.request_body);
+
+        if let Some(status) = self.status {
+            writeln!(output, "\n{} {}", self.http_version, status).ok();
+            write_headers(&mut output, &self.response_headers);
+            write_body(&mut output, &self.response_body);
+        }
+
+        output
+    }
+
+    /// Rebuild the original request so it can be sent again
+    pub fn to_request(&self) -> color_eyre::Result<Request<Body>> {
+        if self.request_body.truncated {
+            eyre::bail!("Request {} body was too large to capture in full", self.id);
+        }
+
+        let url: hyper::Uri = self.url.parse()?;
+        let mut builder = Request::builder().method(self.method.as_str()).uri(
+            url.path_and_query()
+                .map(|path| path.as_str())
+                .unwrap_or("/"),
+        );
+
+        for (name, value) in &self.request_headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        if !self
+            .request_headers
+            .iter()
+            .any(|(name, _)| name == HOST.as_str())
+        {
+            if let Some(authority) = url.authority() {
+                builder = builder.header(HOST, authority.as_str());
+            }
+        }
+
+        Ok(builder.body(Body::from(self.request_body.data.clone()))?)
+    }
+
+    fn har_entry(&self) -> Value {
+        let query: Vec<Value> = url::Url::parse(&self.url)
+            .map(|url| {
+                url.query_pairs()
+                    .map(|(name, value)| json!({ "name": name, "value": value }))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let request_type = header_value(&self.request_headers, CONTENT_TYPE.as_str());
+        let response_type = header_value(&self.response_headers, CONTENT_TYPE.as_str());
+        let wait = self.wait.map(millis).unwrap_or(-1.0);
+        let receive = match (self.total, self.wait) {
+            (Some(total), Some(wait)) => millis(total.saturating_sub(wait)),
+            _ => 0.0,
+        };
+
+        let mut request = json!({
+            "method": self.method,
+            "url": self.url,
+            "httpVersion": self.http_version,
+            "cookies": [],
+            "headers": har_headers(&self.request_headers),
+            "queryString": query,
+            "headersSize": -1,
+            "bodySize": self.request_body.size,
+        });
+        if self.request_body.size > 0 {
+            request["postData"] = json!({
+                "mimeType": request_type.unwrap_or(""),
+                "text": String::from_utf8_lossy(&self.request_body.data),
+            });
+        }
+
+        let mut content = json!({
+            "size": self.response_body.size,
+            "mimeType": response_type.unwrap_or(""),
+        });
+        match self.response_body.text() {
+            Some(text) => content["text"] = json!(text),
+            None => {
+                content["text"] = json!(
+                    base64::engine::general_purpose::STANDARD.encode(&self.response_body.data)
+                );
+                content["encoding"] = json!("base64");
+            }
+        }
+
+        let mut entry = json!({
+            "startedDateTime": format_time(self.started_at),
+            "time": wait + receive,
+            "request": request,
+            "response": {
+                "status": self.status.map(|status| status.as_u16()).unwrap_or(0),
+                "statusText": self
+                    .status
+                    .and_then(|status| status.canonical_reason())
+                    .unwrap_or(""),
+                "httpVersion": self.http_version,
+                "cookies": [],
+                "headers": har_headers(&self.response_headers),
+                "content": content,
+                "redirectURL": header_value(&self.response_headers, "location").unwrap_or(""),
+                "headersSize": -1,
+                "bodySize": self.response_body.size,
+            },
+            "cache": {},
+            "timings": { "send": 0, "wait": wait, "receive": receive },
+        });
+        if let Some(error) = &self.error {
+            entry["comment"] = json!(error);
+        }
+
+        entry
+    }
+}
+
+type SharedExchange = Arc<Mutex<Exchange>>;
+
+/// Ring buffer of the most recent requests for an app
+#[derive(Debug)]
+pub struct Inspector {
+    config: CaptureConfig,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_id: u64,
+    exchanges: VecDeque<SharedExchange>,
+}
+
+impl Inspector {
+    pub fn new(config: CaptureConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State {
+                next_id: 1,
+                exchanges: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Start recording a request if capture is enabled, its body is recorded as it's read
+    ///
+    /// `origin` is the scheme and host the browser used, plus any path prefix the proxy removed.
+    pub fn capture(&self, request: &mut Request<Body>, origin: &str) -> Option<Capture> {
+        if !self.config.enabled {
+            return None;
+        }
+
+        let path = request
+            .uri()
+            .path_and_query()
+            .map(|path| path.as_str())
+            .unwrap_or("/");
+
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+
+        let exchange = Arc::new(Mutex::new(Exchange {
+            id,
+            started_at: SystemTime::now(),
+            method: request.method().to_string(),
+            url: format!("{}{}", origin, path),
+            http_version: format!("{:?}", request.version()),
+            request_headers: header_pairs(request.headers()),
+            request_body: CapturedBody::default(),
+            status: None,
+            response_headers: vec![],
+            response_body: CapturedBody::default(),
+            wait: None,
+            total: None,
+            error: None,
+        }));
+
+        state.exchanges.push_back(exchange.clone());
+        while state.exchanges.len() > self.config.max_requests {
+            state.exchanges.pop_front();
+        }
+        drop(state);
+
+        let body = std::mem::take(request.body_mut());
+        *request.body_mut() = tee(
+            body,
+            self.config.max_body_bytes,
+            exchange.clone(),
+            Side::Request,
+        );
+
+        Some(Capture {
+            exchange,
+            started: Instant::now(),
+            max_body_bytes: self.config.max_body_bytes,
+        })
+    }
+
+    pub fn get(&self, id: u64) -> Option<Exchange> {
+        let state = self.state.lock().unwrap();
+
+        state
+            .exchanges
+            .iter()
+            .map(|exchange| exchange.lock().unwrap())
+            .find(|exchange| exchange.id == id)
+            .map(|exchange| exchange.clone())
+    }
+
+    pub fn exchanges(&self) -> Vec<Exchange> {
+        let state = self.state.lock().unwrap();
+
+        state
+            .exchanges
+            .iter()
+            .map(|exchange| exchange.lock().unwrap().clone())
+            .collect()
+    }
+
+    /// Text listing of captured requests, newest first
+    pub fn summary(&self) -> String {
+        let mut output = String::new();
+
+        for exchange in self.exchanges().iter().rev() {
+            output.push_str(&exchange.summary_line());
+        }
+
+        output
+    }
+
+    /// All captured requests as a HAR 1.2 document
+    pub fn har(&self) -> Value {
+        let entries: Vec<Value> = self.exchanges().iter().map(Exchange::har_entry).collect();
+
+        json!({
+            "log": {
+                "version": "1.2",
+                "creator": { "name": "oxidux", "version": env!("CARGO_PKG_VERSION") },
+                "entries": entries,
+            }
+        })
+    }
+}
+
+/// An in-progress capture of a single request
+pub struct Capture {
+    exchange: SharedExchange,
+    started: Instant,
+    max_body_bytes: usize,
+}
+
+impl Capture {
+    /// Record the response, its body is recorded as it's sent to the browser
+    pub fn finish(self, response: &mut Response<Body>) {
+        let wait = self.started.elapsed();
+
+        {
+            let mut exchange = self.exchange.lock().unwrap();
+            exchange.status = Some(response.status());
+            exchange.response_headers = header_pairs(response.headers());
+            exchange.wait = Some(wait);
+            exchange.error = response
+                .extensions()
+                .get::<UpstreamError>()
+                .map(|error| error.0.clone());
+        }
+
+        // Upgraded connections don't have a body to record
+        if response.status() == StatusCode::SWITCHING_PROTOCOLS {
+            return;
+        }
+
+        let body = std::mem::take(response.body_mut());
+        *response.body_mut() = tee(body, self.max_body_bytes, self.exchange, Side::Response);
+    }
+
+    /// Record that the request failed without a response
+    pub fn fail(self, error: &impl std::fmt::Display) {
+        let mut exchange = self.exchange.lock().unwrap();
+        exchange.wait = Some(self.started.elapsed());
+        exchange.error = Some(format!("{:#}", error));
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Side {
+    Request,
+    Response,
+}
+
+impl Side {
+    fn body(self, exchange: &mut Exchange) -> &mut CapturedBody {
+        match self {
+            Side::Request => &mut exchange.request_body,
+            Side::Response => &mut exchange.response_body,
+        }
+    }
+}
+
+/// Pass a body through unchanged, trailers included, recording the start of it in `exchange`
+fn tee(mut body: Body, limit: usize, exchange: SharedExchange, side: Side) -> Body {
+    let (mut sender, tee) = Body::channel();
+    let started = Instant::now();
+
+    tokio::spawn(async move {
+        while let Some(chunk) = body.data().await {
+            match chunk {
+                Ok(chunk) => {
+                    side.body(&mut exchange.lock().unwrap())
+                        .record(&chunk, limit);
+
+                    if sender.send_data(chunk).await.is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    exchange.lock().unwrap().error = Some(e.to_string());
+                    sender.abort();
+                    return;
+                }
+            }
+        }
+
+        if let Ok(Some(trailers)) = body.trailers().await {
+            sender.send_trailers(trailers).await.ok();
+        }
+
+        let mut exchange = exchange.lock().unwrap();
+        side.body(&mut exchange).complete = true;
+        if let (Side::Response, Some(wait)) = (side, exchange.wait) {
+            exchange.total = Some(wait + started.elapsed());
+        }
+    });
+
+    tee
+}
+
+fn header_pairs(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).to_string(),
+            )
+        })
+        .collect()
+}
+
+fn header_value<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn har_headers(headers: &[(String, String)]) -> Vec<Value> {
+    headers
+        .iter()
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect()
+}
+
+fn write_headers(output: &mut String, headers: &[(String, String)]) {
+    for (name, value) in headers {
+        writeln!(output, "{}: {}", name, value).ok();
+    }
+}
+
+fn write_body(output: &mut String, body: &CapturedBody) {
+    if body.size == 0 {
+        return;
+    }
+
+    output.push('\n');
+    match body.text() {
+        Some(text) => output.push_str(text),
+        None => {
+            writeln!(output, "<{} bytes of binary data>", body.data.len()).ok();
+        }
+    }
+    if body.truncated {
+        write!(output, "\n<truncated, {} bytes total>", body.size).ok();
+    }
+    output.push('\n');
+}
+
+fn format_time(time: SystemTime) -> String {
+    OffsetDateTime::from(time)
+        .format(&Rfc3339)
+        .unwrap_or_default()
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inspector(max_requests: usize, max_body_bytes: usize) -> Inspector {
+        Inspector::new(CaptureConfig {
+            enabled: true,
+            max_requests,
+            max_body_bytes,
+        })
+    }
+
+    async fn send(inspector: &Inspector, path: &str, body: &'static str) {
+        let mut request = Request::post(path)
+            .header(HOST, "myapp.test")
+            .header(CONTENT_TYPE, "text/plain")
+            .body(Body::from(body))
+            .unwrap();
+        let capture = inspector
+            .capture(&mut request, "http://myapp.test")
+            .unwrap();
+        hyper::body::to_bytes(request.into_body()).await.unwrap();
+
+        let mut response = Response::builder()
+            .status(StatusCode::CREATED)
+            .header(CONTENT_TYPE, "text/plain")
+            .body(Body::from("created"))
+            .unwrap();
+        capture.finish(&mut response);
+        hyper::body::to_bytes(response.into_body()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn records_exchanges() {
+        let inspector = inspector(10, 1024);
+
+        send(&inspector, "/items?page=2", "name=thing").await;
+
+        let exchange = inspector.get(1).unwrap();
+        assert_eq!("http://myapp.test/items?page=2", exchange.url);
+        assert_eq!(b"name=thing", exchange.request_body.data.as_slice());
+        assert_eq!(Some(StatusCode::CREATED), exchange.status);
+        assert_eq!(b"created", exchange.response_body.data.as_slice());
+        assert!(exchange.response_body.complete);
+        assert!(exchange.total.is_some());
+        assert!(inspector.summary().contains("201"));
+
+        let request = exchange.to_request().unwrap();
+        assert_eq!("/items?page=2", request.uri());
+        assert_eq!("myapp.test", request.headers()[HOST]);
+    }
+
+    #[tokio::test]
+    async fn keeps_recent_requests() {
+        let inspector = inspector(2, 4);
+
+        for path in &["/one", "/two", "/three"] {
+            send(&inspector, path, "too long").await;
+        }
+
+        let ids: Vec<_> = inspector.exchanges().iter().map(|e| e.id).collect();
+        assert_eq!(vec![2, 3], ids);
+
+        let exchange = inspector.get(3).unwrap();
+        assert!(exchange.request_body.truncated);
+        assert_eq!(8, exchange.request_body.size);
+        assert_eq!(b"too ", exchange.request_body.data.as_slice());
+        assert!(exchange.to_request().is_err());
+    }
+
+    #[tokio::test]
+    async fn exports_har() {
+        let inspector = inspector(10, 1024);
+        send(&inspector, "/items?page=2", "name=thing").await;
+
+        let har = inspector.har();
+        let entry = &har["log"]["entries"][0];
+
+        assert_eq!("1.2", har["log"]["version"]);
+        assert_eq!("POST", entry["request"]["method"]);
+        assert_eq!("page", entry["request"]["queryString"][0]["name"]);
+        assert_eq!("name=thing", entry["request"]["postData"]["text"]);
+        assert_eq!(201, entry["response"]["status"]);
+        assert_eq!("created", entry["response"]["content"]["text"]);
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let inspector = Inspector::new(CaptureConfig::default());
+        let mut request = Request::new(Body::empty());
+
+        assert!(inspector
+            .capture(&mut request, "http://myapp.test")
+            .is_none());
+    }
+}
@@ -13,11 +13,36 @@ pub(crate) enum IpcCommand {
     Connect {
         process_name: Option<String>,
         directory: String,
+        /// Rows and columns of the client's terminal
+        #[serde(default)]
+        size: Option<(u16, u16)>,
     },
     Stop {
         app_name: Option<String>,
         directory: String,
     },
+    Replay {
+        app_name: Option<String>,
+        directory: String,
+        id: u64,
+    },
+    Faults {
+        app_name: Option<String>,
+        directory: String,
+        rule: Option<String>,
+        enabled: bool,
+    },
+    Signal {
+        process_name: Option<String>,
+        directory: String,
+        /// Signal name, e.g. "USR2" or "SIGTERM"
+        signal: String,
+    },
+    History {
+        app_name: Option<String>,
+        directory: String,
+        process_name: Option<String>,
+    },
     Ping,
 }
 
@@ -29,10 +54,15 @@ impl IpcCommand {
         }
     }
 
-    pub fn connect_command(process_name: Option<String>, directory: String) -> Self {
+    pub fn connect_command(
+        process_name: Option<String>,
+        directory: String,
+        size: Option<(u16, u16)>,
+    ) -> Self {
         Self::Connect {
             process_name,
             directory,
+            size,
         }
     }
 
@@ -43,6 +73,48 @@ impl IpcCommand {
         }
     }
 
+    pub fn replay_command(app_name: Option<String>, directory: String, id: u64) -> Self {
+        Self::Replay {
+            app_name,
+            directory,
+            id,
+        }
+    }
+
+    pub fn faults_command(
+        app_name: Option<String>,
+        directory: String,
+        rule: Option<String>,
+        enabled: bool,
+    ) -> Self {
+        Self::Faults {
+            app_name,
+            directory,
+            rule,
+            enabled,
+        }
+    }
+
+    pub fn signal_command(process_name: Option<String>, directory: String, signal: String) -> Self {
+        Self::Signal {
+            process_name,
+            directory,
+            signal,
+        }
+    }
+
+    pub fn history_command(
+        app_name: Option<String>,
+        directory: String,
+        process_name: Option<String>,
+    ) -> Self {
+        Self::History {
+            app_name,
+            directory,
+            process_name,
+        }
+    }
+
     pub fn heartbeat_command() -> Self {
         Self::Ping
     }
@@ -1,16 +1,19 @@
-use crate::config;
+use crate::app::App;
+use crate::config::{self, ProcessBackend, StopSignal};
 use crate::ipc_command::IpcCommand;
 
 use color_eyre::Result;
 use eyre::{eyre, Context};
+use std::convert::TryFrom;
 use std::str;
 use tokio::fs;
-use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::{UnixListener, UnixStream};
 
 use crate::ipc_response::IpcResponse;
 use crate::process::Process;
 use crate::process_manager::ProcessManager;
+use crate::proxy;
 
 fn read_command(mut connection: UnixStream) {
     tokio::spawn(async move {
@@ -20,7 +23,7 @@ fn read_command(mut connection: UnixStream) {
         let command = parse_command(&mut reader).await;
 
         match command {
-            Ok(command) => run_command(&command, writer).await,
+            Ok(command) => run_command(&command, reader, writer).await,
             Err(e) => eprintln!("{:#}", e),
         }
     });
@@ -35,9 +38,10 @@ async fn parse_command(reader: &mut (impl AsyncBufRead + Unpin)) -> color_eyre::
     parse_incoming_command(&msg).context("Failed to parse command, is it valid JSON?")
 }
 
-async fn run_command<T>(command: &IpcCommand, writer: T)
+async fn run_command<R, W>(command: &IpcCommand, reader: R, writer: W)
 where
-    T: AsyncWrite + Unpin,
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
 {
     match command {
         IpcCommand::Restart {
@@ -47,17 +51,39 @@ where
         IpcCommand::Connect {
             process_name,
             directory,
-        } => connect_output(process_name, directory, writer).await,
+            size,
+        } => connect_output(process_name, directory, *size, reader, writer).await,
         IpcCommand::Stop {
             app_name,
             directory,
         } => stop_app(app_name, directory, writer).await,
+        IpcCommand::Replay {
+            app_name,
+            directory,
+            id,
+        } => replay_request(app_name, directory, *id, writer).await,
+        IpcCommand::Faults {
+            app_name,
+            directory,
+            rule,
+            enabled,
+        } => toggle_faults(app_name, directory, rule, *enabled, writer).await,
+        IpcCommand::Signal {
+            process_name,
+            directory,
+            signal,
+        } => signal_process(process_name, directory, signal, writer).await,
+        IpcCommand::History {
+            app_name,
+            directory,
+            process_name,
+        } => process_history(app_name, directory, process_name, writer).await,
         IpcCommand::Ping => heartbeat_response(writer).await,
     }
 }
 
 fn parse_incoming_command(buf: &[u8]) -> Result<IpcCommand> {
-    let raw_json = str::from_utf8(&buf)?;
+    let raw_json = str::from_utf8(buf)?;
 
     let command: IpcCommand = serde_json::from_str(raw_json)?;
 
@@ -93,6 +119,8 @@ where
 async fn connect_output(
     process_name: &Option<String>,
     directory: &str,
+    size: Option<(u16, u16)>,
+    reader: impl AsyncRead + Unpin,
     writer: impl AsyncWrite + Unpin,
 ) {
     let process = {
@@ -100,6 +128,12 @@ async fn connect_output(
         lookup_process(&process_manager, process_name, directory).await
     };
 
+    if let Some(process) = &process {
+        if process.process_backend().await == ProcessBackend::Native {
+            return attach_terminal(process, size, reader, writer).await;
+        }
+    }
+
     let process = process.ok_or_else(|| eyre!("Failed to find app to connect to"));
     print_error(process_response(&process, writer).await);
 
@@ -108,10 +142,45 @@ async fn connect_output(
     }
 }
 
+/// Relay the terminal of a natively run process over the connection
+async fn attach_terminal(
+    process: &Process,
+    size: Option<(u16, u16)>,
+    reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+) {
+    let terminal = match process.terminal().await {
+        Some(terminal) => terminal,
+        None => {
+            let message = format!("{} hasn't been started", process.name().await);
+            return print_error(write_response(&mut writer, &IpcResponse::NotFound(message)).await);
+        }
+    };
+
+    if let Some((rows, cols)) = size {
+        terminal.resize(rows, cols);
+    }
+
+    let response = IpcResponse::Terminal {
+        process_name: process.name().await,
+    };
+    if let Err(e) = write_response(&mut writer, &response).await {
+        return eprintln!("{:#}", e);
+    }
+    // A newline marks where the response ends and terminal output begins
+    if let Err(e) = writer.write_all(b"\n").await {
+        return eprintln!("Failed to start terminal: {}", e);
+    }
+
+    if let Err(e) = terminal.attach(reader, writer).await {
+        eprintln!("Terminal connection failed: {}", e);
+    }
+}
+
 async fn restart_app(
     process_name: &Option<String>,
     directory: &str,
-    writer: impl AsyncWrite + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
 ) {
     let process = {
         let process_manager = ProcessManager::global_read().await;
@@ -120,7 +189,16 @@ async fn restart_app(
 
     let process = process.ok_or_else(|| eyre!("Failed to find app to restart"));
 
-    print_error(process_response(&process, writer).await);
+    match &process {
+        // There's no tmux session to attach to
+        Ok(process) if process.process_backend().await == ProcessBackend::Native => {
+            let message = format!("Restarting {}", process.name().await);
+            print_error(write_response(&mut writer, &IpcResponse::Status(message)).await);
+            // The client reads until the connection closes, which shouldn't wait on the restart
+            writer.shutdown().await.ok();
+        }
+        _ => print_error(process_response(&process, writer).await),
+    }
 
     match process {
         Ok(process) => {
@@ -143,7 +221,7 @@ async fn stop_app(app_name: &Option<String>, directory: &str, mut writer: impl A
     let response = match app {
         Some(app) => {
             app.stop().await;
-            (&mut process_manager).remove_app_by_name(app.name());
+            process_manager.remove_app_by_name(app.name());
             format!("Stopping {}", app.name())
         }
         None => "Failed to find app to stop".to_string(),
@@ -154,6 +232,126 @@ async fn stop_app(app_name: &Option<String>, directory: &str, mut writer: impl A
     }
 }
 
+async fn replay_request(
+    app_name: &Option<String>,
+    directory: &str,
+    id: u64,
+    mut writer: impl AsyncWrite + Unpin,
+) {
+    let app = find_app(app_name, directory).await;
+
+    let response = match app {
+        Some(app) => match proxy::replay(&app, id).await {
+            Ok(response) => {
+                let status = response.status();
+                // Read the body in the background so the capture of the replay is complete
+                tokio::spawn(hyper::body::to_bytes(response.into_body()));
+
+                format!("Replayed request {}: {}", id, status)
+            }
+            Err(e) => format!("Failed to replay request {}: {:#}", id, e),
+        },
+        None => "Failed to find app to replay request for".to_string(),
+    };
+
+    if let Err(e) = write_response(&mut writer, &IpcResponse::Status(response)).await {
+        eprintln!("{:#}", e);
+    }
+}
+
+async fn toggle_faults(
+    app_name: &Option<String>,
+    directory: &str,
+    rule: &Option<String>,
+    enabled: bool,
+    mut writer: impl AsyncWrite + Unpin,
+) {
+    let app = find_app(app_name, directory).await;
+
+    let response = match app {
+        Some(app) => match app.faults().set_enabled(rule.as_deref(), enabled) {
+            Ok(rules) if rules.is_empty() => format!("{} has no fault rules", app.name()),
+            Ok(rules) => format!(
+                "Turned {} faults for {}: {}",
+                if enabled { "on" } else { "off" },
+                app.name(),
+                rules.join(", ")
+            ),
+            Err(e) => e,
+        },
+        None => "Failed to find app to change faults for".to_string(),
+    };
+
+    if let Err(e) = write_response(&mut writer, &IpcResponse::Status(response)).await {
+        eprintln!("{:#}", e);
+    }
+}
+
+async fn signal_process(
+    process_name: &Option<String>,
+    directory: &str,
+    signal: &str,
+    mut writer: impl AsyncWrite + Unpin,
+) {
+    let process = {
+        let process_manager = ProcessManager::global_read().await;
+        lookup_process(&process_manager, process_name, directory).await
+    };
+
+    let response = match (process, StopSignal::try_from(signal.to_string())) {
+        (None, _) => IpcResponse::NotFound("Failed to find process to signal".to_string()),
+        (Some(_), Err(e)) => IpcResponse::NotFound(e),
+        (Some(process), Ok(StopSignal(signal))) => match process.send_signal(signal).await {
+            Ok(()) => IpcResponse::Status(format!("Sent {} to {}", signal, process.name().await)),
+            Err(e) => IpcResponse::NotFound(e),
+        },
+    };
+
+    if let Err(e) = write_response(&mut writer, &response).await {
+        eprintln!("{:#}", e);
+    }
+}
+
+async fn process_history(
+    app_name: &Option<String>,
+    directory: &str,
+    process_name: &Option<String>,
+    mut writer: impl AsyncWrite + Unpin,
+) {
+    let app = find_app(app_name, directory).await;
+
+    let response = match app {
+        // Static and external apps run nothing of their own
+        Some(app) if app.processes.is_empty() => {
+            IpcResponse::NotFound(format!("{} has no managed processes", app.name()))
+        }
+        Some(app) => match app.history(process_name.as_deref()).await {
+            Some(history) => IpcResponse::Status(history.trim_end().to_string()),
+            None => IpcResponse::NotFound(format!(
+                "{} has no process named {}",
+                app.name(),
+                process_name.as_deref().unwrap_or_default()
+            )),
+        },
+        None => IpcResponse::NotFound("Failed to find app to show history for".to_string()),
+    };
+
+    if let Err(e) = write_response(&mut writer, &response).await {
+        eprintln!("{:#}", e);
+    }
+}
+
+/// Look up an app by name, or by directory if no name is given
+async fn find_app(app_name: &Option<String>, directory: &str) -> Option<App> {
+    let process_manager = ProcessManager::global_read().await;
+
+    match app_name {
+        Some(app_name) => process_manager.find_app_by_name(app_name),
+        None => process_manager.find_app_for_directory(directory),
+    }
+    .cloned()
+}
+
 async fn write_response(
     writer: &mut (impl AsyncWrite + Unpin),
     response: &IpcResponse,
@@ -12,6 +12,10 @@ pub enum IpcResponse {
         tmux_session: String,
     },
     Status(String),
+    /// The process's terminal follows on the connection
+    Terminal {
+        process_name: String,
+    },
 }
 
 impl IpcResponse {
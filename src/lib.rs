@@ -6,7 +6,9 @@ use tokio::runtime::Runtime;
 
 pub mod proxy;
 
+mod alias;
 mod app;
+mod backend;
 mod process;
 pub mod process_manager;
 use crate::process_manager::ProcessManager;
@@ -15,13 +17,17 @@ use crate::config::Config;
 pub mod client;
 #[cfg(target_os = "macos")]
 mod dns;
+mod faults;
+mod header_rules;
 mod host_resolver;
+mod inspector;
 pub mod ipc_command;
 mod ipc_listener;
 mod ipc_response;
 mod output;
 mod procfile;
 mod signals;
+mod tls;
 mod tmux;
 
 fn server_running() -> bool {
@@ -42,11 +48,18 @@ pub fn run_server(config: Config) {
     runtime.block_on(async {
         ProcessManager::initialize(&config);
 
+        for conflict in config.alias_conflicts().await {
+            eprintln!("Warning: alias {}", conflict);
+        }
+
         tokio::spawn(ProcessManager::monitor_idle_timeout());
 
         #[cfg(target_os = "macos")]
-        dns::start_dns_server(config.general.dns_port, &config.general.domain)
-            .expect("Failed to start DNS server");
+        dns::start_dns_server(
+            config.general.dns_port,
+            &ProcessManager::global_read().await.all_domains().await,
+        )
+        .expect("Failed to start DNS server");
 
         let shutdown_rx = signals::ctrlc_listener();
 
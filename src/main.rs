@@ -1,5 +1,9 @@
 use clap::{App, AppSettings, Arg, SubCommand};
 use oxidux::config;
+use std::path::Path;
+
+/// Config file the server reads unless given another one
+const DEFAULT_CONFIG_FILE: &str = "apps.toml";
 
 fn main() -> color_eyre::Result<()> {
     let matches = App::new("oxidux")
@@ -11,7 +15,7 @@ fn main() -> color_eyre::Result<()> {
                     Arg::with_name("config")
                         .value_name("CONFIG_FILE")
                         .help("App config file")
-                        .default_value("apps.toml"),
+                        .default_value(DEFAULT_CONFIG_FILE),
                 ),
         )
         .subcommand(
@@ -39,6 +43,91 @@ fn main() -> color_eyre::Result<()> {
                     .help("Name of app to stop (defaults to app for current directory)"),
             ),
         )
+        .subcommand(
+            SubCommand::with_name("replay")
+                .about("Send a captured request to the app again")
+                .arg(
+                    Arg::with_name("id")
+                        .value_name("ID")
+                        .help("Request number, as listed at /__oxidux__/requests")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("app")
+                        .long("app")
+                        .value_name("APP_NAME")
+                        .help(
+                            "App the request was sent to (defaults to app for current directory)",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("faults")
+                .about("Turn fault rules on or off")
+                .arg(
+                    Arg::with_name("state")
+                        .value_name("on|off")
+                        .possible_values(&["on", "off"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("rule")
+                        .value_name("RULE")
+                        .help("Name of fault rule to change (defaults to all of them)"),
+                )
+                .arg(
+                    Arg::with_name("app")
+                        .long("app")
+                        .value_name("APP_NAME")
+                        .help("App to change (defaults to app for current directory)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("signal")
+                .about("Send a signal to a process")
+                .arg(
+                    Arg::with_name("signal")
+                        .value_name("SIGNAL")
+                        .help("Signal to send, e.g. USR2 or SIGTERM")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("process")
+                        .value_name("PROCESS_NAME")
+                        .help("Name of process to signal"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("history")
+                .about("Show how recent runs of processes ended, with their last output")
+                .arg(
+                    Arg::with_name("process")
+                        .value_name("PROCESS_NAME")
+                        .help("Name of process to show (defaults to all of them)"),
+                )
+                .arg(
+                    Arg::with_name("app")
+                        .long("app")
+                        .value_name("APP_NAME")
+                        .help("App to show (defaults to app for current directory)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("ca")
+                .about("Print path to the local HTTPS certificate authority")
+                .arg(
+                    Arg::with_name("export")
+                        .long("export")
+                        .value_name("FILE")
+                        .help("Copy the CA certificate to FILE"),
+                )
+                .arg(
+                    Arg::with_name("config")
+                        .long("config")
+                        .value_name("CONFIG_FILE")
+                        .help("Config file the server was started with (defaults to apps.toml if it exists)"),
+                ),
+        )
         .setting(AppSettings::SubcommandRequiredElseHelp)
         .get_matches();
 
@@ -60,6 +149,38 @@ fn main() -> color_eyre::Result<()> {
             let app_name = matches.value_of("app_name");
             oxidux::client::stop_app(app_name)?;
         }
+        ("replay", Some(matches)) => {
+            let id = matches
+                .value_of("id")
+                .unwrap()
+                .parse()
+                .map_err(|_| eyre::eyre!("Request ID must be a number"))?;
+            let app_name = matches.value_of("app");
+            oxidux::client::replay_request(id, app_name)?;
+        }
+        ("faults", Some(matches)) => {
+            let enabled = matches.value_of("state") == Some("on");
+            let rule = matches.value_of("rule");
+            let app_name = matches.value_of("app");
+            oxidux::client::toggle_faults(enabled, rule, app_name)?;
+        }
+        ("signal", Some(matches)) => {
+            let signal = matches.value_of("signal").unwrap();
+            let process_name = matches.value_of("process");
+            oxidux::client::signal_process(signal, process_name)?;
+        }
+        ("history", Some(matches)) => {
+            let process_name = matches.value_of("process");
+            let app_name = matches.value_of("app");
+            oxidux::client::process_history(process_name, app_name)?;
+        }
+        ("ca", Some(matches)) => {
+            let export_path = matches.value_of("export");
+            let config_file = matches
+                .value_of("config")
+                .or_else(|| Some(DEFAULT_CONFIG_FILE).filter(|file| Path::new(file).is_file()));
+            oxidux::client::ca_certificate(config_file, export_path)?;
+        }
         (command, _) => panic!("Unrecognized command {}", command),
     }
 
@@ -1,6 +1,7 @@
 use crate::process::Process;
 use ansi_term::Color;
 use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, BufReader, Lines};
+use tokio::task::JoinHandle;
 
 type OutputStream<T> = Lines<T>;
 pub struct Output {
@@ -9,7 +10,11 @@ pub struct Output {
 }
 
 impl Output {
-    pub fn for_stream<T: 'static + AsyncRead + Unpin + Send>(fifo: T, process: Process) {
+    /// Log lines from the stream until it ends, which the returned handle waits for
+    pub fn for_stream<T: 'static + AsyncRead + Unpin + Send>(
+        fifo: T,
+        process: Process,
+    ) -> JoinHandle<()> {
         tokio::spawn(async {
             let index = process.port().await;
             let name = pick_color(index).paint(process.name().await).to_string();
@@ -17,7 +22,7 @@ impl Output {
 
             let output = Output { name, process };
             output.setup_writer(stream).await
-        });
+        })
     }
 
     async fn setup_writer<T>(self, mut stream: OutputStream<T>)
@@ -27,14 +32,14 @@ impl Output {
         while let Some(line) = stream.next_line().await.transpose() {
             match line {
                 Ok(line) => {
+                    // Terminals end lines with "\r\n"
+                    let line = line.trim_end_matches('\r').to_string();
                     println!("{}: {}", pick_color(1).paint(&self.name), line);
-                    self.process.output_line(line);
+                    self.process.output_line(line).await;
                 }
                 Err(error) => eprintln!("Error in log output: {}", error),
             }
         }
-
-        self.process.process_died().await;
     }
 }
 
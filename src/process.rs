@@ -1,19 +1,18 @@
+use std::convert::TryFrom;
 use std::env;
-use std::io::BufRead;
-use std::path::PathBuf;
-use std::str;
-use std::sync::Arc;
-use std::time::Duration;
+use std::fmt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 use nix::sys::signal::{self, Signal};
-use nix::sys::stat;
 use nix::unistd::{self, Pid};
 
 use async_stream::stream;
-use eyre::{bail, eyre, Context};
-use futures::Stream;
+use eyre::Context;
+use futures::{Future, Stream};
 use tokio::{
-    fs::{self, File},
     sync::{
         broadcast::{self, error::RecvError},
         RwLock, RwLockReadGuard, RwLockWriteGuard,
@@ -21,9 +20,20 @@ use tokio::{
     time::timeout,
 };
 
-use crate::config;
-use crate::output::Output;
-use crate::tmux;
+use crate::backend::Backend;
+use crate::config::{self, ProcessBackend};
+
+mod exit_watch;
+mod history;
+mod native;
+mod restart;
+mod tmux_session;
+
+use history::History;
+pub(crate) use history::{Run, Trigger};
+use restart::{Decision, Restarts};
+
+pub(crate) use native::Terminal;
 
 #[derive(Clone, Debug)]
 pub struct Process {
@@ -31,8 +41,6 @@ pub struct Process {
 }
 
 const LOCK_TIMEOUT: Duration = Duration::from_secs(2);
-const PID_STOP_TIMEOUT: Duration = Duration::from_secs(20);
-const WATCH_INTERVAL: Duration = Duration::from_millis(100);
 
 #[derive(Debug, Clone)]
 pub(crate) enum RunState {
@@ -46,6 +54,48 @@ pub(crate) enum RunState {
     Terminating(Pid),
     /// Same as `Terminating`, but with the intention to restart
     Restarting(Pid),
+    /// Process exited on its own and will be started again after the delay
+    Backoff(Duration),
+    /// Process exited too often recently, with this many automatic restarts, and is left stopped
+    CrashLooping(usize),
+}
+
+/// How a process exited
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum ExitStatus {
+    Code(i32),
+    Signal(i32),
+    /// The status couldn't be found out, e.g. from a tmux session that was killed
+    Unknown,
+}
+
+impl ExitStatus {
+    pub(crate) fn is_success(&self) -> bool {
+        *self == ExitStatus::Code(0)
+    }
+}
+
+impl From<std::process::ExitStatus> for ExitStatus {
+    fn from(status: std::process::ExitStatus) -> Self {
+        match (status.code(), status.signal()) {
+            (Some(code), _) => ExitStatus::Code(code),
+            (None, Some(signal)) => ExitStatus::Signal(signal),
+            (None, None) => ExitStatus::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExitStatus::Code(code) => write!(f, "exit code {}", code),
+            ExitStatus::Signal(signal) => match Signal::try_from(*signal) {
+                Ok(signal) => write!(f, "signal {}", signal),
+                Err(_) => write!(f, "signal {}", signal),
+            },
+            ExitStatus::Unknown => write!(f, "unknown status"),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -53,10 +103,26 @@ struct Inner {
     app_name: String,
     process_name: String,
     port: u16,
+    socket: Option<PathBuf>,
     command: String,
     directory: String,
     state: RunState,
+    /// Number of the current or last run, which exit watchers are tied to
+    run: u64,
     output_channel: broadcast::Sender<(Process, String)>,
+    backend: ProcessBackend,
+    /// Terminal the process last ran on, when run natively
+    terminal: Option<Terminal>,
+    restarts: Restarts,
+    /// Locked on its own so output can be recorded while holding only a read lock
+    history: Mutex<History>,
+    /// Why the process was last asked to stop
+    stop_trigger: Trigger,
+    /// Signals sent in turn to stop the process, waiting `stop_timeout` after each
+    stop_signals: Vec<Signal>,
+    stop_timeout: Duration,
+    /// Whether stopping, rather than only restarting, goes on to the later signals
+    escalate_on_stop: bool,
 }
 
 impl Process {
@@ -65,16 +131,42 @@ impl Process {
         process_name: String,
         command: String,
         port: u16,
+        general: &config::ProxyConfig,
     ) -> Self {
         let (output_channel, _output_receiver) = broadcast::channel(50);
+        let directory = expand_path(&app_config.directory);
+        let socket = app_config.socket.as_ref().map(|template| {
+            let placeholders = SocketPlaceholders {
+                app_name: &app_config.name,
+                process_name: &process_name,
+                directory: &directory,
+                config_dir: &general.config_dir,
+            };
+            socket_path(template, &placeholders)
+        });
+        let backend = general.process_backend;
+
+        let restarts = Restarts::new(&app_config.restart, &process_name);
+        let (stop_signals, stop_timeout) = app_config.stop.sequence_for(&process_name);
+
         let data = Inner {
             app_name: app_config.name.clone(),
             process_name,
             port,
+            socket,
             command,
-            directory: expand_path(&app_config.directory),
+            directory,
             state: RunState::Stopped,
+            run: 0,
             output_channel,
+            backend,
+            terminal: None,
+            restarts,
+            history: Mutex::default(),
+            stop_trigger: Trigger::Stop,
+            stop_signals,
+            stop_timeout,
+            escalate_on_stop: app_config.stop.escalate_on_stop,
         };
 
         Process {
@@ -102,55 +194,6 @@ impl Process {
         self.inner().await.app_name.clone()
     }
 
-    async fn kill_tmux_session(&self) -> Result<(), String> {
-        tmux::kill_session(&self.tmux_session().await)
-            .await
-            .map(drop)
-            .map_err(|e| format!("Cleaning up old tmux session failed with error {}", e))
-    }
-
-    async fn respawn_tmux_session(&self) -> color_eyre::Result<()> {
-        let session_name = self.tmux_session().await;
-        let shell_args = self.shell_args();
-
-        let result = tmux::respawn_window(&session_name, &shell_args.await)
-            .await
-            .context("Error trying to run respawn command")?;
-
-        if !result.success() {
-            bail!("Non-zero return status from respawn-session");
-        }
-
-        let pids = tmux::list_sessions().await?.stdout;
-
-        let pid = BufRead::lines(&pids[..])
-            .find_map(|line| match line {
-                Err(_) => None,
-                Ok(line) => {
-                    let parts: Vec<&str> = line.splitn(2, '|').collect();
-
-                    match parts[..] {
-                        [session, pid] if session == session_name => Some(String::from(pid)),
-                        _ => None,
-                    }
-                }
-            })
-            .ok_or_else(|| eyre!("Failed to find PID for session"))?;
-
-        self.set_pid(
-            pid.parse()
-                .with_context(|| format!("\"{}\" is not a valid pid", pid))?,
-        )
-        .await;
-
-        self.watch_for_exit();
-        self.pipe_output()
-            .await
-            .unwrap_or_else(|e| println!("{}", e));
-
-        Ok(())
-    }
-
     pub async fn start(&self) -> Result<(), String> {
         if !matches!(self.run_state().await, RunState::Stopped) {
             return Err("Ignoring start request - process is not stopped".to_string());
@@ -158,124 +201,180 @@ impl Process {
 
         self.set_run_state(RunState::Starting).await;
 
-        if self.respawn_tmux_session().await.is_ok() {
-            eprintln!("Respawned existing session");
-            // Bail out if respawning worked
-            return Ok(());
+        let backend = self.inner().await.backend;
+        match backend {
+            ProcessBackend::Tmux => tmux_session::start(self).await,
+            ProcessBackend::Native => native::start(self).await,
         }
-        eprintln!("Starting new session");
-
-        // Clean up any existing tmux sessions with conflicting names
-        self.kill_tmux_session().await?;
-
-        let args = self.shell_args().await;
-        eprintln!("Starting command {}", args[4]);
-
-        let child_pid = tmux::new_session(&self.tmux_session().await, &args)
-            .await
-            .map_err(|_| "Failed to start app process")?
-            .stdout;
+    }
 
-        // Set pid using output from Tmux custom format
-        let child_pid = str::from_utf8(&child_pid)
-            .map_err(|e| format!("{}", e))?
-            .trim();
+    pub async fn restart(&self) {
+        eprintln!("restarting");
 
-        self.set_pid(
-            child_pid
-                .parse()
-                .map_err(|_| format!("\"{}\" is not a valid pid", child_pid))?,
-        )
-        .await;
+        match self.run_state().await {
+            RunState::Restarting(_) | RunState::Starting => {
+                eprintln!("Ignoring restart request, process is in invalid state");
+            }
+            RunState::Stopped => self.start().await.unwrap_or_else(|e| eprintln!("{}", e)),
+            RunState::Backoff(_) | RunState::CrashLooping(_) => {
+                // Restarting by hand gives the process a clean slate
+                self.inner_mut().await.restarts.reset();
+                self.set_run_state(RunState::Stopped).await;
+                self.start().await.unwrap_or_else(|e| eprintln!("{}", e));
+            }
+            RunState::Running(pid) | RunState::Terminating(pid) => {
+                self.set_run_state(RunState::Restarting(pid)).await;
+                self.send_stop_signals(pid, true).await;
+            }
+        }
+    }
 
-        self.watch_for_exit();
-        self.pipe_output().await?;
+    /// Send the first stop signal, and if `escalate` the others in turn while the process is up
+    async fn send_stop_signals(&self, pid: Pid, escalate: bool) {
+        let (signals, timeout) = {
+            let inner = self.inner().await;
+            (inner.stop_signals.clone(), inner.stop_timeout)
+        };
 
-        Ok(())
+        signal_pid(pid, signals[0]).unwrap_or_else(|e| eprintln!("{}", e));
+        if escalate {
+            self.escalate_after_timeout(pid, signals[1..].to_vec(), timeout);
+        }
     }
 
-    /// Capture process output for our logging system
-    async fn pipe_output(&self) -> Result<(), String> {
-        let fifo_path = self.setup_fifo().await.map_err(|e| e.to_string())?;
+    fn escalate_after_timeout(&self, pid: Pid, signals: Vec<Signal>, timeout: Duration) {
+        let process = self.clone();
+        tokio::spawn(async move {
+            for signal in signals {
+                tokio::time::sleep(timeout).await;
+
+                match process.run_state().await {
+                    RunState::Restarting(newpid) | RunState::Terminating(newpid)
+                        if pid == newpid =>
+                    {
+                        signal_pid(pid, signal).unwrap_or_else(|e| eprintln!("{}", e));
+                    }
+                    // If process is in new state or pid has changed don't try to kill
+                    _ => return,
+                };
+            }
+        });
+    }
 
-        tmux::pipe_pane(&fifo_path)
-            .await
-            .map_err(|_| "Failed to set up tmux output pipe")?;
+    /// Send a signal to the process group of a running process
+    pub(crate) async fn send_signal(&self, signal: Signal) -> Result<(), String> {
+        match self.run_state().await {
+            RunState::Running(pid) | RunState::Terminating(pid) | RunState::Restarting(pid) => {
+                signal_pid(pid, signal).map_err(String::from)
+            }
+            _ => Err(format!("{} isn't running", self.name().await)),
+        }
+    }
 
-        let fifo = File::open(&fifo_path)
-            .await
-            .map_err(|e| format!("Couldn't open FIFO, got {}", e))?;
-        Output::for_stream(fifo, self.clone());
+    /// Call `process_died` for the run once `exit` completes
+    fn on_exit(&self, run: u64, exit: impl Future<Output = ExitStatus> + Send + 'static) {
+        let process = self.clone();
 
-        Ok(())
+        tokio::spawn(async move {
+            let status = exit.await;
+            process.process_died(run, status).await;
+        });
     }
 
-    async fn setup_fifo(&self) -> color_eyre::Result<PathBuf> {
-        let pipe_name = {
-            let inner = self.inner().await;
-            format!("{}_{}.pipe", inner.app_name, inner.process_name)
-        };
+    /// Handle the process exiting, which is ignored if `run` has already ended
+    async fn process_died(&self, run: u64, status: ExitStatus) {
+        let previous_state = {
+            let mut inner = self.inner_mut().await;
+            if inner.run != run {
+                return;
+            }
 
-        let path = config::config_dir().join(pipe_name);
-        fs::remove_file(&path).await.ok();
+            let trigger = match inner.state {
+                RunState::Running(_) => Trigger::Crash,
+                RunState::Terminating(_) => inner.stop_trigger,
+                RunState::Restarting(_) => Trigger::Restart,
+                _ => return,
+            };
+            inner
+                .history
+                .get_mut()
+                .unwrap()
+                .stopped(status, trigger, SystemTime::now());
 
-        unistd::mkfifo(&path, stat::Mode::S_IRWXU)
-            .wrap_err("Failed to set up process output fifo")?;
+            std::mem::replace(&mut inner.state, RunState::Stopped)
+        };
+        eprintln!("{} exited with {}", self.name().await, status);
 
-        Ok(path)
+        match previous_state {
+            RunState::Restarting(_) => self.start().await.unwrap_or_else(|e| eprintln!("{}", e)),
+            RunState::Running(_) => self.restart_after_exit(status).await,
+            _ => {}
+        }
     }
 
-    pub async fn restart(&self) {
-        eprintln!("restarting");
-
-        match self.run_state().await {
-            RunState::Restarting(_) | RunState::Starting => {
-                eprintln!("Ignoring restart request, process is in invalid state");
+    /// Apply the restart policy to a process that exited without being stopped
+    async fn restart_after_exit(&self, status: ExitStatus) {
+        let decision = self
+            .inner_mut()
+            .await
+            .restarts
+            .after_exit(&status, Instant::now());
+
+        match decision {
+            Decision::Stay => {}
+            Decision::Restart(delay) => {
+                eprintln!("Restarting {} in {:?}", self.name().await, delay);
+                self.set_run_state(RunState::Backoff(delay)).await;
+                self.start_after(delay);
             }
-            RunState::Stopped => self.start().await.unwrap_or_else(|e| eprintln!("{}", e)),
-            RunState::Running(pid) | RunState::Terminating(pid) => {
-                self.set_run_state(RunState::Restarting(pid)).await;
-                self.kill_after_timout(pid);
-
-                signal_pid(pid, Signal::SIGINT).unwrap_or_else(|e| eprintln!("{}", e));
+            Decision::GiveUp(restarts) => {
+                eprintln!(
+                    "{} is crash looping after {} restarts, leaving it stopped",
+                    self.name().await,
+                    restarts
+                );
+                self.set_run_state(RunState::CrashLooping(restarts)).await;
             }
         }
     }
 
-    /// Force kill the process if it doesn't respond to our earlier SIGINT
-    fn kill_after_timout(&self, pid: Pid) {
+    fn start_after(&self, delay: Duration) {
         let process = self.clone();
+
         tokio::spawn(async move {
-            tokio::time::sleep(PID_STOP_TIMEOUT).await;
+            tokio::time::sleep(delay).await;
 
-            match process.run_state().await {
-                RunState::Restarting(newpid) | RunState::Terminating(newpid) if pid == newpid => {
-                    signal_pid(pid, Signal::SIGKILL).unwrap_or_else(|e| eprintln!("{}", e));
-                }
-                _ => {
-                    // If process is in new state or pid has changed don't try to kill
-                }
-            };
+            // Stopped or restarted by hand in the meantime
+            if !matches!(process.run_state().await, RunState::Backoff(_)) {
+                return;
+            }
+
+            process.set_run_state(RunState::Stopped).await;
+            process.start().await.unwrap_or_else(|e| eprintln!("{}", e));
         });
     }
 
-    pub async fn process_died(&self) {
-        let previous_state = self.run_state().await;
-        self.set_run_state(RunState::Stopped).await;
-
-        if let RunState::Restarting(_) = previous_state {
-            self.start().await.unwrap_or_else(|e| eprintln!("{}", e));
-        }
+    pub async fn stop(&self) {
+        self.stop_for(Trigger::Stop).await
     }
 
-    pub async fn stop(&self) {
+    /// Stop the process, recording `trigger` as the reason in its history
+    pub(crate) async fn stop_for(&self, trigger: Trigger) {
+        self.inner_mut().await.stop_trigger = trigger;
+
         match self.run_state().await {
-            RunState::Starting | RunState::Stopped => {
+            RunState::Stopped => {}
+            RunState::Starting => {
                 eprintln!("Ignoring stop request, process is in invalid state");
             }
+            RunState::Backoff(_) | RunState::CrashLooping(_) => {
+                self.set_run_state(RunState::Stopped).await;
+            }
             RunState::Running(pid) | RunState::Terminating(pid) | RunState::Restarting(pid) => {
                 self.set_run_state(RunState::Terminating(pid)).await;
-                signal_pid(pid, Signal::SIGINT).unwrap_or_else(|e| eprintln!("{}", e));
+                // Apps shutting down slowly are left to finish unless configured otherwise
+                let escalate = self.inner().await.escalate_on_stop;
+                self.send_stop_signals(pid, escalate).await;
             }
         }
     }
@@ -284,6 +383,22 @@ impl Process {
         matches!(self.run_state().await, RunState::Running(_))
     }
 
+    /// Automatic restarts before the process was given up on, if it's crash looping
+    pub(crate) async fn crash_loop_restarts(&self) -> Option<usize> {
+        match self.run_state().await {
+            RunState::CrashLooping(restarts) => Some(restarts),
+            _ => None,
+        }
+    }
+
+    /// Delay before the process is started again, if it's waiting to be
+    pub(crate) async fn restart_delay(&self) -> Option<Duration> {
+        match self.run_state().await {
+            RunState::Backoff(delay) => Some(delay),
+            _ => None,
+        }
+    }
+
     pub(crate) async fn run_state(&self) -> RunState {
         self.inner().await.state.clone()
     }
@@ -293,11 +408,17 @@ impl Process {
     }
 
     async fn shell_args(&self) -> [String; 5] {
+        let socket_export = match self.socket().await {
+            Some(socket) => format!("export SOCKET={}; ", shell_quote(&socket.to_string_lossy())),
+            None => String::new(),
+        };
+
         let full_command = format!(
-            "exec bash -c 'cd {directory}; export PORT={port}; {command}'",
+            "exec bash -c 'cd {directory}; export PORT={port}; {socket_export}{command}'",
             directory = self.directory().await,
             command = self.command().await,
-            port = self.port().await
+            port = self.port().await,
+            socket_export = socket_export,
         );
 
         let shell = env::var("SHELL").unwrap_or_else(|_| "/bin/sh".into());
@@ -309,10 +430,33 @@ impl Process {
         self.name().await
     }
 
+    pub(crate) async fn process_backend(&self) -> ProcessBackend {
+        self.inner().await.backend
+    }
+
+    /// Terminal of a natively run process, which `connect` relays
+    pub(crate) async fn terminal(&self) -> Option<Terminal> {
+        self.inner().await.terminal.clone()
+    }
+
     pub async fn port(&self) -> u16 {
         self.inner().await.port
     }
 
+    pub async fn socket(&self) -> Option<PathBuf> {
+        self.inner().await.socket.clone()
+    }
+
+    /// Address requests for this process should be sent to
+    pub(crate) async fn backend(&self) -> Backend {
+        let inner = self.inner().await;
+
+        match &inner.socket {
+            Some(socket) => Backend::Socket(socket.clone()),
+            None => Backend::Port(inner.port),
+        }
+    }
+
     pub async fn directory(&self) -> String {
         self.inner().await.directory.clone()
     }
@@ -321,43 +465,31 @@ impl Process {
         self.inner().await.command.clone()
     }
 
-    async fn pid(&self) -> Option<Pid> {
-        match self.run_state().await {
-            RunState::Running(pid) | RunState::Restarting(pid) | RunState::Terminating(pid) => {
-                Some(pid)
-            }
-            _ => None,
-        }
-    }
-
-    async fn set_pid(&self, pid: u32) {
+    /// Record the pid of a new run, returning the run's number
+    async fn set_pid(&self, pid: u32) -> u64 {
         eprintln!("Setting pid for {} to {}", self.name().await, pid);
         let pid = Pid::from_raw(pid as i32);
 
-        self.set_run_state(RunState::Running(pid)).await
-    }
-
-    fn watch_for_exit(&self) {
-        let process = self.clone();
+        let mut inner = self.inner_mut().await;
+        inner.state = RunState::Running(pid);
+        inner.run += 1;
+        inner.history.get_mut().unwrap().started(SystemTime::now());
 
-        let watcher = async move {
-            let mut interval = tokio::time::interval(WATCH_INTERVAL);
-
-            loop {
-                interval.tick().await;
+        inner.run
+    }
 
-                if let Some(pid) = process.pid().await {
-                    if signal::kill(pid, None).is_err() {
-                        eprintln!("Process died");
-                        process.process_died().await;
+    /// Recent runs, oldest first
+    pub(crate) async fn history(&self) -> Vec<Run> {
+        self.inner().await.history.lock().unwrap().runs()
+    }
 
-                        return;
-                    }
-                }
-            }
-        };
+    /// The last run, if it ended with the process exiting on its own
+    pub(crate) async fn last_crash(&self) -> Option<Run> {
+        if self.is_running().await {
+            return None;
+        }
 
-        tokio::spawn(watcher);
+        self.history().await.pop().filter(Run::crashed)
     }
 
     pub async fn name(&self) -> String {
@@ -388,14 +520,15 @@ impl Process {
         Box::pin(raw_stream)
     }
 
-    /// Forwards stdout from process to any registered watchers
-    pub fn output_line(&self, line: String) {
-        let process = self.clone();
-        tokio::spawn(async move {
-            let output_channel = &process.inner().await.output_channel;
+    /// Forwards stdout from process to any registered watchers, and keeps it in the history
+    pub async fn output_line(&self, line: String) {
+        let inner = match timeout(LOCK_TIMEOUT, self.inner.read()).await {
+            Ok(inner) => inner,
+            Err(_) => return eprintln!("Timed out waiting on process read lock, dropping output"),
+        };
 
-            output_channel.send((process.clone(), line))
-        });
+        inner.history.lock().unwrap().output(&line);
+        inner.output_channel.send((self.clone(), line)).ok();
     }
 }
 
@@ -412,6 +545,45 @@ fn negate_pid(pid: Pid) -> Pid {
     Pid::from_raw(-pid_id)
 }
 
+/// Values for the placeholders in a socket path template
+struct SocketPlaceholders<'a> {
+    app_name: &'a str,
+    process_name: &'a str,
+    directory: &'a str,
+    config_dir: &'a Path,
+}
+
+/// Fill in placeholders in a socket path template
+fn socket_path(template: &str, placeholders: &SocketPlaceholders) -> PathBuf {
+    let path = template
+        .replace("{app}", placeholders.app_name)
+        .replace("{process}", placeholders.process_name)
+        .replace("{directory}", placeholders.directory)
+        .replace("{config_dir}", &placeholders.config_dir.to_string_lossy());
+
+    PathBuf::from(expand_path(&path))
+}
+
+/// Double quote a value for the `bash -c` in `shell_args`, whose script is in single quotes
+fn shell_quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+
+    for c in value.chars() {
+        match c {
+            '"' | '$' | '`' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            // Close the outer single quotes around an escaped one
+            '\'' => quoted.push_str("'\\''"),
+            _ => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
 fn expand_path(input_path: &str) -> String {
     match shellexpand::full(input_path) {
         Ok(expanded_path) => expanded_path.to_string(),
@@ -442,4 +614,36 @@ mod tests {
 
         assert_eq!(path::PathBuf::from(result), home_dir.join("foo/bar"))
     }
+
+    #[test]
+    fn socket_path_fills_placeholders() {
+        let placeholders = SocketPlaceholders {
+            app_name: "blog",
+            process_name: "web",
+            directory: "/src/blog",
+            config_dir: Path::new("/etc/oxidux"),
+        };
+
+        let result = socket_path("{directory}/tmp/{app}-{process}.sock", &placeholders);
+        assert_eq!(PathBuf::from("/src/blog/tmp/blog-web.sock"), result);
+
+        let result = socket_path("{config_dir}/{app}.sock", &placeholders);
+        assert_eq!(PathBuf::from("/etc/oxidux/blog.sock"), result);
+    }
+
+    #[test]
+    fn shell_quote_survives_both_shells() {
+        let value = r#"/tmp/my app/it's "$HOME" `x` \ ; rm.sock"#;
+        let script = format!(
+            "exec bash -c 'export SOCKET={}; printf %s \"$SOCKET\"'",
+            shell_quote(value)
+        );
+
+        let output = std::process::Command::new("sh")
+            .args(["-c", &script])
+            .output()
+            .unwrap();
+
+        assert_eq!(value, String::from_utf8_lossy(&output.stdout));
+    }
 }
@@ -0,0 +1,137 @@
+// Find out when processes that aren't our children exit
+use std::time::Duration;
+
+use nix::sys::signal;
+use nix::unistd::Pid;
+
+/// How often to check processes when they can't be waited on
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Wait for the process to exit, through a pidfd where the kernel supports them
+#[cfg(target_os = "linux")]
+pub(super) async fn exited(pid: Pid) {
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::FromRawFd;
+
+    use tokio::io::unix::AsyncFd;
+
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0) };
+    if fd == -1 {
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() == Some(libc::ESRCH) {
+            // Already gone
+            return;
+        }
+
+        eprintln!("Couldn't open pidfd ({}), polling process instead", error);
+        return poll(pid).await;
+    }
+
+    // The pidfd becomes readable once the process has exited
+    let pidfd = unsafe { File::from_raw_fd(fd as i32) };
+    match AsyncFd::new(pidfd) {
+        Ok(pidfd) => drop(pidfd.readable().await),
+        Err(e) => {
+            eprintln!("Couldn't watch pidfd ({}), polling process instead", e);
+            poll(pid).await
+        }
+    }
+}
+
+/// Wait for the process to exit, through a kqueue with an `EVFILT_PROC` filter
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+pub(super) async fn exited(pid: Pid) {
+    use std::fs::File;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    use nix::errno::Errno;
+    use nix::sys::event::{kevent, kqueue, EventFilter, EventFlag, FilterFlag, KEvent};
+    use tokio::io::unix::AsyncFd;
+
+    let kq = match kqueue() {
+        Ok(fd) => unsafe { File::from_raw_fd(fd) },
+        Err(e) => {
+            eprintln!("Couldn't open kqueue ({}), polling process instead", e);
+            return poll(pid).await;
+        }
+    };
+
+    let exit = KEvent::new(
+        pid.as_raw() as usize,
+        EventFilter::EVFILT_PROC,
+        EventFlag::EV_ADD | EventFlag::EV_ONESHOT,
+        FilterFlag::NOTE_EXIT,
+        0,
+        0,
+    );
+    match kevent(kq.as_raw_fd(), &[exit], &mut [], 0) {
+        Ok(_) => {}
+        // Already gone
+        Err(Errno::ESRCH) => return,
+        Err(e) => {
+            eprintln!(
+                "Couldn't watch process with kqueue ({}), polling instead",
+                e
+            );
+            return poll(pid).await;
+        }
+    }
+
+    // The kqueue becomes readable once the exit event is pending
+    match AsyncFd::new(kq) {
+        Ok(kq) => drop(kq.readable().await),
+        Err(e) => {
+            eprintln!("Couldn't watch kqueue ({}), polling process instead", e);
+            poll(pid).await
+        }
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+)))]
+pub(super) async fn exited(pid: Pid) {
+    poll(pid).await
+}
+
+/// Fallback for when the process can't be watched, checking whether it still exists
+async fn poll(pid: Pid) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+    while signal::kill(pid, None).is_ok() {
+        interval.tick().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn notices_exit() {
+        let mut child = Command::new("sleep").arg("0.2").spawn().unwrap();
+        let pid = Pid::from_raw(child.id() as i32);
+
+        // Reap the child elsewhere, like tmux does for its panes
+        let reaper = std::thread::spawn(move || child.wait());
+
+        timeout(Duration::from_secs(5), exited(pid)).await.unwrap();
+        assert!(reaper.join().unwrap().unwrap().success());
+    }
+}
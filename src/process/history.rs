@@ -0,0 +1,188 @@
+// Remember how recent runs of a process went
+use std::collections::VecDeque;
+use std::fmt::{self, Write};
+use std::time::SystemTime;
+
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use super::ExitStatus;
+
+/// Runs kept per process
+const MAX_RUNS: usize = 10;
+/// Output lines kept per run
+const OUTPUT_LINES: usize = 20;
+
+/// What ended a run
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Trigger {
+    Restart,
+    Stop,
+    /// The app wasn't requested for longer than `idle_timeout_secs`
+    IdleStop,
+    /// The process exited without being asked to
+    Crash,
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Trigger::Restart => "restart",
+            Trigger::Stop => "stop",
+            Trigger::IdleStop => "idle stop",
+            Trigger::Crash => "crash",
+        };
+
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Run {
+    number: u64,
+    started_at: SystemTime,
+    stopped_at: Option<SystemTime>,
+    exit_status: Option<ExitStatus>,
+    trigger: Option<Trigger>,
+    /// Last lines of output
+    output: VecDeque<String>,
+}
+
+impl Run {
+    /// Whether the run ended without the process being asked to stop
+    pub(crate) fn crashed(&self) -> bool {
+        self.trigger == Some(Trigger::Crash)
+    }
+
+    fn summary_line(&self) -> String {
+        let mut line = format!("#{} started {}", self.number, format_time(self.started_at));
+
+        match (self.stopped_at, self.exit_status, self.trigger) {
+            (Some(stopped_at), Some(status), Some(trigger)) => write!(
+                line,
+                ", stopped {} by {} with {}",
+                format_time(stopped_at),
+                trigger,
+                status
+            )
+            .unwrap(),
+            _ => line.push_str(", running"),
+        }
+
+        line
+    }
+
+    /// Summary followed by the run's last output
+    pub(crate) fn details(&self) -> String {
+        let mut details = self.summary_line();
+        details.push('\n');
+
+        for line in &self.output {
+            writeln!(details, "    {}", line).unwrap();
+        }
+
+        details
+    }
+}
+
+#[derive(Debug, Default)]
+pub(super) struct History {
+    runs: VecDeque<Run>,
+    started: u64,
+}
+
+impl History {
+    pub(super) fn started(&mut self, now: SystemTime) {
+        self.started += 1;
+        if self.runs.len() == MAX_RUNS {
+            self.runs.pop_front();
+        }
+
+        self.runs.push_back(Run {
+            number: self.started,
+            started_at: now,
+            stopped_at: None,
+            exit_status: None,
+            trigger: None,
+            output: VecDeque::new(),
+        });
+    }
+
+    /// Add output to the latest run, which output still arriving after it stopped belongs to
+    pub(super) fn output(&mut self, line: &str) {
+        if let Some(run) = self.runs.back_mut() {
+            if run.output.len() == OUTPUT_LINES {
+                run.output.pop_front();
+            }
+            run.output.push_back(line.to_string());
+        }
+    }
+
+    pub(super) fn stopped(&mut self, status: ExitStatus, trigger: Trigger, now: SystemTime) {
+        if let Some(run) = self.runs.back_mut().filter(|run| run.stopped_at.is_none()) {
+            run.stopped_at = Some(now);
+            run.exit_status = Some(status);
+            run.trigger = Some(trigger);
+        }
+    }
+
+    pub(super) fn runs(&self) -> Vec<Run> {
+        self.runs.iter().cloned().collect()
+    }
+}
+
+fn format_time(time: SystemTime) -> String {
+    OffsetDateTime::from(time)
+        .format(&Rfc3339)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn records_runs() {
+        let mut history = History::default();
+        let start = UNIX_EPOCH + Duration::from_secs(60);
+
+        history.started(start);
+        history.output("booting");
+        history.stopped(ExitStatus::Code(1), Trigger::Crash, start);
+        history.output("late line");
+        history.started(start);
+
+        let runs = history.runs();
+        assert_eq!(2, runs.len());
+        assert!(runs[0].crashed());
+        assert_eq!(
+            "#1 started 1970-01-01T00:01:00Z, stopped 1970-01-01T00:01:00Z by crash with exit \
+             code 1\n    booting\n    late line\n",
+            runs[0].details()
+        );
+        assert_eq!(
+            "#2 started 1970-01-01T00:01:00Z, running\n",
+            runs[1].details()
+        );
+    }
+
+    #[test]
+    fn keeps_recent_runs_and_output() {
+        let mut history = History::default();
+
+        for _ in 0..MAX_RUNS + 2 {
+            history.started(SystemTime::now());
+        }
+        for line in 0..OUTPUT_LINES + 5 {
+            history.output(&line.to_string());
+        }
+
+        let runs = history.runs();
+        assert_eq!(MAX_RUNS, runs.len());
+        assert_eq!(3, runs[0].number);
+        let last = runs.last().unwrap();
+        assert_eq!(OUTPUT_LINES, last.output.len());
+        assert_eq!("5", last.output[0]);
+    }
+}
@@ -0,0 +1,234 @@
+// Run processes on a pseudo-terminal of their own, without tmux
+use std::collections::VecDeque;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+
+use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+use nix::pty::{self, Winsize};
+use nix::unistd;
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio::process::{Child, Command};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use super::{ExitStatus, Process};
+use crate::output::Output;
+
+/// Output kept for clients that connect later, so they see what's on screen
+const SCROLLBACK_BYTES: usize = 64 * 1024;
+
+const DEFAULT_SIZE: Winsize = Winsize {
+    ws_row: 24,
+    ws_col: 80,
+    ws_xpixel: 0,
+    ws_ypixel: 0,
+};
+
+pub(super) async fn start(process: &Process) -> Result<(), String> {
+    let args = process.shell_args().await;
+    eprintln!("Starting command {}", args[4]);
+
+    let (child, terminal, output) =
+        spawn(&args).map_err(|e| format!("Failed to start app process: {}", e))?;
+    let pid = child.id().ok_or("App process exited immediately")?;
+
+    process.inner_mut().await.terminal = Some(terminal);
+    let run = process.set_pid(pid).await;
+    Output::for_stream(output, process.clone());
+    process.on_exit(run, wait_for_exit(child));
+
+    Ok(())
+}
+
+/// Wait on the process, which as our child also has to be reaped
+async fn wait_for_exit(mut child: Child) -> ExitStatus {
+    match child.wait().await {
+        Ok(status) => status.into(),
+        Err(e) => {
+            eprintln!("Failed to wait for process: {}", e);
+            ExitStatus::Unknown
+        }
+    }
+}
+
+/// Start the command in a new session on a new terminal, returning its output for logging
+fn spawn(args: &[String]) -> io::Result<(Child, Terminal, DuplexStream)> {
+    let pty = pty::openpty(Some(&DEFAULT_SIZE), None)?;
+    let (master, slave) = unsafe { (File::from_raw_fd(pty.master), File::from_raw_fd(pty.slave)) };
+
+    fcntl(master.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+    fcntl(master.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+
+    let mut command = Command::new(&args[0]);
+    command
+        .args(&args[1..])
+        .stdin(Stdio::from(slave.try_clone()?))
+        .stdout(Stdio::from(slave.try_clone()?))
+        .stderr(Stdio::from(slave));
+    if env::var_os("TERM").is_none() {
+        command.env("TERM", "xterm-256color");
+    }
+
+    // A session of its own puts the process in its own group, which signals are sent to, with
+    // the terminal as its controlling terminal
+    unsafe {
+        command.pre_exec(|| {
+            unistd::setsid()?;
+            if libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(())
+        });
+    }
+
+    let child = command.spawn()?;
+    // Close our copies of the terminal, so reads fail once the process exits
+    drop(command);
+
+    let terminal = Terminal {
+        master: Arc::new(AsyncFd::new(master)?),
+        output: broadcast::channel(100).0,
+        scrollback: Arc::new(Mutex::new(VecDeque::new())),
+    };
+    let (writer, reader) = tokio::io::duplex(SCROLLBACK_BYTES);
+    tokio::spawn(terminal.clone().copy_output(writer));
+
+    Ok((child, terminal, reader))
+}
+
+/// Pseudo-terminal a native process runs on
+#[derive(Clone, Debug)]
+pub(crate) struct Terminal {
+    master: Arc<AsyncFd<File>>,
+    /// Output for connected clients, an empty chunk means the process has exited
+    output: broadcast::Sender<Vec<u8>>,
+    scrollback: Arc<Mutex<VecDeque<u8>>>,
+}
+
+impl Terminal {
+    /// Relay the terminal to a client until either of them closes it
+    pub(crate) async fn attach(
+        &self,
+        mut reader: impl AsyncRead + Unpin,
+        mut writer: impl AsyncWrite + Unpin,
+    ) -> io::Result<()> {
+        let (scrollback, mut output) = {
+            let scrollback = self.scrollback.lock().unwrap();
+            (Vec::from(scrollback.clone()), self.output.subscribe())
+        };
+        writer.write_all(&scrollback).await?;
+        writer.flush().await?;
+
+        let mut buffer = vec![0; 4096];
+
+        loop {
+            tokio::select! {
+                chunk = output.recv() => match chunk {
+                    Ok(chunk) if chunk.is_empty() => return Ok(()),
+                    Ok(chunk) => {
+                        writer.write_all(&chunk).await?;
+                        writer.flush().await?;
+                    }
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                read = reader.read(&mut buffer) => match read? {
+                    0 => return Ok(()),
+                    read => self.write(&buffer[..read]).await?,
+                },
+            }
+        }
+    }
+
+    /// Set the size of the terminal, which the process hears about with a SIGWINCH
+    pub(crate) fn resize(&self, rows: u16, cols: u16) {
+        let size = Winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ..DEFAULT_SIZE
+        };
+
+        unsafe {
+            libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ as _, &size);
+        }
+    }
+
+    async fn copy_output(self, mut lines: DuplexStream) {
+        let mut buffer = vec![0; 4096];
+
+        // Reads fail once every process using the terminal has exited
+        while let Ok(read @ 1..) = self.read(&mut buffer).await {
+            let chunk = &buffer[..read];
+
+            {
+                let mut scrollback = self.scrollback.lock().unwrap();
+                scrollback.extend(chunk);
+                let excess = scrollback.len().saturating_sub(SCROLLBACK_BYTES);
+                scrollback.drain(..excess);
+
+                // Sent with the lock held so attaching clients don't miss or repeat anything
+                self.output.send(chunk.to_vec()).ok();
+            }
+
+            lines.write_all(chunk).await.ok();
+        }
+
+        self.output.send(Vec::new()).ok();
+    }
+
+    async fn read(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.master.readable().await?;
+
+            if let Ok(result) = guard.try_io(|master| master.get_ref().read(buffer)) {
+                return result;
+            }
+        }
+    }
+
+    async fn write(&self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let mut guard = self.master.writable().await?;
+
+            if let Ok(result) = guard.try_io(|master| master.get_ref().write(data)) {
+                data = &data[result?..];
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn relays_terminal() {
+        let args = ["/bin/sh", "-c", "read line; echo \"got $line\""].map(String::from);
+        let (mut child, terminal, mut output) = spawn(&args).unwrap();
+
+        let (client, server) = tokio::io::duplex(1024);
+        let (server_reader, server_writer) = tokio::io::split(server);
+        let relay =
+            tokio::spawn(async move { terminal.attach(server_reader, server_writer).await });
+
+        let (mut client_reader, mut client_writer) = tokio::io::split(client);
+        client_writer.write_all(b"hello\n").await.unwrap();
+
+        let mut relayed = Vec::new();
+        client_reader.read_to_end(&mut relayed).await.unwrap();
+        assert!(String::from_utf8_lossy(&relayed).contains("got hello"));
+        relay.await.unwrap().unwrap();
+
+        let mut logged = String::new();
+        output.read_to_string(&mut logged).await.unwrap();
+        assert!(logged.contains("got hello"));
+        assert!(child.wait().await.unwrap().success());
+    }
+}
@@ -0,0 +1,153 @@
+// Decide whether to restart processes that exit on their own
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::ExitStatus;
+use crate::config::{RestartConfig, RestartPolicy};
+
+/// Delay before the first automatic restart, doubled for each restart after it
+const BASE_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, PartialEq)]
+pub(super) enum Decision {
+    /// Leave the process stopped
+    Stay,
+    /// Start the process again after the delay
+    Restart(Duration),
+    /// The process has been restarted too often recently, with this many restarts
+    GiveUp(usize),
+}
+
+/// A process's restart policy and its recent automatic restarts
+#[derive(Debug)]
+pub(super) struct Restarts {
+    policy: RestartPolicy,
+    max_retries: usize,
+    window: Duration,
+    recent: VecDeque<Instant>,
+}
+
+impl Restarts {
+    pub(super) fn new(config: &RestartConfig, process_name: &str) -> Self {
+        Self {
+            policy: config.policy_for(process_name),
+            max_retries: config.max_retries as usize,
+            window: Duration::from_secs(config.window_secs),
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Decide what happens after the process exited without being asked to
+    pub(super) fn after_exit(&mut self, status: &ExitStatus, now: Instant) -> Decision {
+        let restart = match self.policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => !status.is_success(),
+            RestartPolicy::Always => true,
+        };
+        if !restart {
+            return Decision::Stay;
+        }
+
+        while self
+            .recent
+            .front()
+            .is_some_and(|restart| now.duration_since(*restart) > self.window)
+        {
+            self.recent.pop_front();
+        }
+
+        if self.recent.len() >= self.max_retries {
+            return Decision::GiveUp(self.recent.len());
+        }
+
+        let delay = BASE_DELAY
+            .checked_mul(1 << min(self.recent.len(), 16))
+            .map_or(MAX_DELAY, |delay| min(delay, MAX_DELAY));
+        self.recent.push_back(now);
+
+        Decision::Restart(delay)
+    }
+
+    /// Forget earlier restarts, when the process is started by hand
+    pub(super) fn reset(&mut self) {
+        self.recent.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn restarts(policy: RestartPolicy) -> Restarts {
+        let config = RestartConfig {
+            policy,
+            max_retries: 3,
+            window_secs: 60,
+            ..Default::default()
+        };
+
+        Restarts::new(&config, "web")
+    }
+
+    #[test]
+    fn follows_policy() {
+        let now = Instant::now();
+        let failed = ExitStatus::Code(1);
+        let succeeded = ExitStatus::Code(0);
+
+        assert_eq!(
+            Decision::Stay,
+            restarts(RestartPolicy::Never).after_exit(&failed, now)
+        );
+        assert_eq!(
+            Decision::Stay,
+            restarts(RestartPolicy::OnFailure).after_exit(&succeeded, now)
+        );
+        assert_eq!(
+            Decision::Restart(BASE_DELAY),
+            restarts(RestartPolicy::OnFailure).after_exit(&ExitStatus::Signal(9), now)
+        );
+        assert_eq!(
+            Decision::Restart(BASE_DELAY),
+            restarts(RestartPolicy::Always).after_exit(&succeeded, now)
+        );
+    }
+
+    #[test]
+    fn backs_off_and_gives_up() {
+        let mut restarts = restarts(RestartPolicy::Always);
+        let start = Instant::now();
+        let status = ExitStatus::Code(1);
+
+        assert_eq!(
+            Decision::Restart(Duration::from_secs(1)),
+            restarts.after_exit(&status, start)
+        );
+        assert_eq!(
+            Decision::Restart(Duration::from_secs(2)),
+            restarts.after_exit(&status, start + Duration::from_secs(5))
+        );
+        assert_eq!(
+            Decision::Restart(Duration::from_secs(4)),
+            restarts.after_exit(&status, start + Duration::from_secs(10))
+        );
+        assert_eq!(
+            Decision::GiveUp(3),
+            restarts.after_exit(&status, start + Duration::from_secs(20))
+        );
+
+        // Restarts drop out of the window over time
+        assert_eq!(
+            Decision::Restart(Duration::from_secs(4)),
+            restarts.after_exit(&status, start + Duration::from_secs(61))
+        );
+
+        restarts.reset();
+        assert_eq!(
+            Decision::Restart(Duration::from_secs(1)),
+            restarts.after_exit(&status, start + Duration::from_secs(62))
+        );
+    }
+}
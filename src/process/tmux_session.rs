@@ -0,0 +1,195 @@
+// Run processes in tmux sessions
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::str;
+use std::time::Duration;
+
+use eyre::{bail, eyre, Context};
+use nix::sys::stat;
+use nix::unistd::{self, Pid};
+use tokio::fs::{self, File};
+
+use super::{exit_watch, ExitStatus, Process};
+use crate::config;
+use crate::output::Output;
+use crate::tmux;
+
+/// How often, and how many times, to check whether tmux has marked an exited pane dead
+const DEAD_PANE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const DEAD_PANE_POLLS: usize = 40;
+
+pub(super) async fn start(process: &Process) -> Result<(), String> {
+    if respawn_session(process).await.is_ok() {
+        eprintln!("Respawned existing session");
+        // Bail out if respawning worked
+        return Ok(());
+    }
+    eprintln!("Starting new session");
+
+    // Clean up any existing tmux sessions with conflicting names
+    kill_session(process).await?;
+
+    let args = process.shell_args().await;
+    eprintln!("Starting command {}", args[4]);
+
+    let child_pid = tmux::new_session(&process.tmux_session().await, &args)
+        .await
+        .map_err(|_| "Failed to start app process")?
+        .stdout;
+
+    // Set pid using output from Tmux custom format
+    let child_pid = str::from_utf8(&child_pid)
+        .map_err(|e| format!("{}", e))?
+        .trim();
+
+    let pid = child_pid
+        .parse()
+        .map_err(|_| format!("\"{}\" is not a valid pid", child_pid))?;
+    let run = process.set_pid(pid).await;
+
+    watch_for_exit(process, run, pid);
+    pipe_output(process).await?;
+
+    Ok(())
+}
+
+async fn kill_session(process: &Process) -> Result<(), String> {
+    tmux::kill_session(&process.tmux_session().await)
+        .await
+        .map(drop)
+        .map_err(|e| format!("Cleaning up old tmux session failed with error {}", e))
+}
+
+async fn respawn_session(process: &Process) -> color_eyre::Result<()> {
+    let session_name = process.tmux_session().await;
+    let shell_args = process.shell_args();
+
+    let result = tmux::respawn_window(&session_name, &shell_args.await)
+        .await
+        .context("Error trying to run respawn command")?;
+
+    if !result.success() {
+        bail!("Non-zero return status from respawn-session");
+    }
+
+    let pids = tmux::list_sessions().await?.stdout;
+
+    let pid = BufRead::lines(&pids[..])
+        .find_map(|line| match line {
+            Err(_) => None,
+            Ok(line) => {
+                let parts: Vec<&str> = line.splitn(2, '|').collect();
+
+                match parts[..] {
+                    [session, pid] if session == session_name => Some(String::from(pid)),
+                    _ => None,
+                }
+            }
+        })
+        .ok_or_else(|| eyre!("Failed to find PID for session"))?;
+
+    let pid = pid
+        .parse()
+        .with_context(|| format!("\"{}\" is not a valid pid", pid))?;
+    let run = process.set_pid(pid).await;
+
+    watch_for_exit(process, run, pid);
+    pipe_output(process)
+        .await
+        .unwrap_or_else(|e| println!("{}", e));
+
+    Ok(())
+}
+
+/// Capture process output for our logging system
+async fn pipe_output(process: &Process) -> Result<(), String> {
+    let fifo_path = setup_fifo(process).await.map_err(|e| e.to_string())?;
+
+    tmux::pipe_pane(&fifo_path)
+        .await
+        .map_err(|_| "Failed to set up tmux output pipe")?;
+
+    let fifo = File::open(&fifo_path)
+        .await
+        .map_err(|e| format!("Couldn't open FIFO, got {}", e))?;
+    Output::for_stream(fifo, process.clone());
+
+    Ok(())
+}
+
+/// Find out how the pane's process exited, from the dead pane tmux keeps around
+async fn exit_status(process: &Process) -> ExitStatus {
+    let session = process.tmux_session().await;
+    let mut interval = tokio::time::interval(DEAD_PANE_POLL_INTERVAL);
+
+    // The process can be gone before tmux has reaped it and marked the pane dead
+    for _ in 0..DEAD_PANE_POLLS {
+        interval.tick().await;
+
+        let output = match tmux::pane_dead_status(&session).await {
+            Ok(output) if output.status.success() => output.stdout,
+            _ => return ExitStatus::Unknown,
+        };
+
+        if let Some(status) = parse_dead_pane(&String::from_utf8_lossy(&output)) {
+            return status;
+        }
+    }
+
+    ExitStatus::Unknown
+}
+
+/// Parse "dead|status|signal" from tmux, or None while the pane is still alive
+fn parse_dead_pane(output: &str) -> Option<ExitStatus> {
+    let mut parts = output.trim().splitn(3, '|');
+    if parts.next() != Some("1") {
+        return None;
+    }
+
+    let status = match (parts.next(), parts.next()) {
+        (_, Some(signal)) if !signal.is_empty() => signal
+            .parse()
+            .map_or(ExitStatus::Unknown, ExitStatus::Signal),
+        (Some(code), _) => code.parse().map_or(ExitStatus::Unknown, ExitStatus::Code),
+        _ => ExitStatus::Unknown,
+    };
+
+    Some(status)
+}
+
+async fn setup_fifo(process: &Process) -> color_eyre::Result<PathBuf> {
+    let pipe_name = {
+        let inner = process.inner().await;
+        format!("{}_{}.pipe", inner.app_name, inner.process_name)
+    };
+
+    let path = config::config_dir().join(pipe_name);
+    fs::remove_file(&path).await.ok();
+
+    unistd::mkfifo(&path, stat::Mode::S_IRWXU).wrap_err("Failed to set up process output fifo")?;
+
+    Ok(path)
+}
+
+/// Watch the pid, since the process is a child of the tmux server rather than ours
+fn watch_for_exit(process: &Process, run: u64, pid: u32) {
+    let session = process.clone();
+
+    process.on_exit(run, async move {
+        exit_watch::exited(Pid::from_raw(pid as i32)).await;
+        exit_status(&session).await
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_dead_pane() {
+        assert_eq!(parse_dead_pane("0||\n"), None);
+        assert_eq!(parse_dead_pane("1|3|\n"), Some(ExitStatus::Code(3)));
+        assert_eq!(parse_dead_pane("1||9\n"), Some(ExitStatus::Signal(9)));
+        assert_eq!(parse_dead_pane("1||\n"), Some(ExitStatus::Unknown));
+    }
+}
@@ -1,19 +1,33 @@
 use eyre::Context;
 use once_cell::sync::OnceCell;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use tokio::time::sleep;
 
 use crate::app::App;
-use crate::config::Config;
+use crate::config::{self, Config};
 
 #[derive(Debug)]
 pub struct ProcessManager {
     pub apps: Vec<App>,
     config: Config,
     next_port: u16,
+    /// App configs read from disk, kept until any of the files change
+    app_configs: Mutex<Option<CachedConfigs>>,
 }
 
+#[derive(Debug)]
+struct CachedConfigs {
+    /// Config files when they were read, see `config_files`
+    files: Vec<ConfigFile>,
+    configs: Arc<Vec<config::App>>,
+}
+
+/// Path, modification time and size of an app config file
+type ConfigFile = (PathBuf, SystemTime, u64);
+
 const PORT_START: u16 = 7500;
 const MONITORING_INTERVAL_SECS: u64 = 30;
 const LOCK_TIMEOUT_SECS: u64 = 2;
@@ -32,6 +46,7 @@ impl ProcessManager {
             apps,
             config,
             next_port: PORT_START,
+            app_configs: Mutex::new(None),
         }
     }
 
@@ -45,7 +60,7 @@ impl ProcessManager {
             for app in &process_manager.apps {
                 if app.last_hit().await.elapsed().as_secs() > idle_timout_secs {
                     eprintln!("App {} is idle, removing it", app.name());
-                    app.stop().await;
+                    app.stop_idle().await;
                     expired_apps.push(app.name().to_string());
                 }
             }
@@ -96,9 +111,9 @@ impl ProcessManager {
     }
 
     pub fn add_app(&mut self, new_app: crate::config::App) -> App {
-        let app = App::from_config(&new_app, self.next_port, self.config.general.domain.clone());
+        let app = App::from_config(&new_app, self.next_port, &self.config.general);
 
-        self.next_port += 1;
+        self.next_port += app.allocated_ports();
 
         self.apps.push(app.clone());
 
@@ -108,15 +123,14 @@ impl ProcessManager {
     pub fn find_app_for_directory(&self, directory: &str) -> Option<&App> {
         self.apps
             .iter()
-            .find(|app| directory.starts_with(&app.directory()))
+            .find(|app| directory.starts_with(app.directory()))
     }
 
     /// Stop all apps
     pub async fn shutdown(&mut self) {
+        // Stopping idle apps too keeps pending automatic restarts from happening
         for app in self.apps.iter() {
-            if app.is_running().await {
-                app.stop().await;
-            }
+            app.stop().await;
         }
 
         // Poll processes until they stop
@@ -139,6 +153,48 @@ impl ProcessManager {
     pub(crate) fn remove_app_by_name(&mut self, app_name: &str) {
         self.apps.retain(|a| a.name() != app_name);
     }
+
+    /// Configs of all apps, only parsed again once a file has been added, removed or edited
+    pub(crate) async fn app_configs(&self) -> Arc<Vec<config::App>> {
+        let files = config_files(&self.config.app_config_dir()).await;
+
+        if let Some(cached) = &*self.app_configs.lock().unwrap() {
+            if Some(&cached.files) == files.as_ref() {
+                return cached.configs.clone();
+            }
+        }
+
+        let configs = Arc::new(self.config.app_configs().await);
+        if let Some(files) = files {
+            *self.app_configs.lock().unwrap() = Some(CachedConfigs {
+                files,
+                configs: configs.clone(),
+            });
+        }
+
+        configs
+    }
+
+    /// Global TLDs followed by any others that apps use instead
+    pub(crate) async fn all_domains(&self) -> Vec<String> {
+        self.config.domains_for(&self.app_configs().await)
+    }
+}
+
+/// Stat every file in the apps directory, which is much cheaper than parsing them
+async fn config_files(dir: &Path) -> Option<Vec<ConfigFile>> {
+    let mut entries = tokio::fs::read_dir(dir).await.ok()?;
+    let mut files = Vec::new();
+
+    while let Some(entry) = entries.next_entry().await.ok()? {
+        let path = entry.path();
+        // Follow symlinks, so edits to the file they point at are noticed
+        let metadata = tokio::fs::metadata(&path).await.ok()?;
+        files.push((path, metadata.modified().ok()?, metadata.len()));
+    }
+    files.sort();
+
+    Some(files)
 }
 
 #[cfg(test)]
@@ -164,4 +220,143 @@ mod tests {
         // Verify that we didn't reuse the port
         assert_ne!(first_port, second_port);
     }
+
+    #[tokio::test]
+    async fn per_process_port_assignment() {
+        let config = Config::default();
+        let mut manager = ProcessManager::new(&config);
+        let commands = [
+            ("web", "server"),
+            ("webpack", "bin/webpack"),
+            ("worker", "jobs"),
+        ]
+        .iter()
+        .map(|(name, command)| (name.to_string(), command.to_string()))
+        .collect();
+        let app_config = crate::config::App {
+            name: "multi".to_string(),
+            command_config: crate::config::CommandConfig::Commands(commands),
+            per_process_ports: true,
+            ..Default::default()
+        };
+
+        let app = manager.add_app(app_config);
+
+        let backend_port = |port| crate::backend::Backend::Port(port);
+        assert_eq!(app.port(), PORT_START);
+        assert_eq!(
+            app.backend_for_subdomain(None).await,
+            backend_port(PORT_START)
+        );
+        assert_eq!(
+            app.backend_for_subdomain(Some("webpack")).await,
+            backend_port(PORT_START + 1)
+        );
+        assert_eq!(
+            app.backend_for_subdomain(Some("worker")).await,
+            backend_port(PORT_START + 2)
+        );
+        assert_eq!(
+            app.backend_for_subdomain(Some("tenant")).await,
+            backend_port(PORT_START)
+        );
+
+        // Next app shouldn't overlap with the extra process ports
+        let app2 = manager.add_app(crate::config::App::default());
+        assert_eq!(app2.port(), PORT_START + 3);
+    }
+
+    #[tokio::test]
+    async fn process_subdomains_only_from_plain_subdomains() {
+        use crate::alias::Alias;
+        use crate::backend::Backend;
+        use crate::host_resolver::process_subdomain;
+        use std::convert::TryFrom;
+
+        let config = Config::default();
+        let mut manager = ProcessManager::new(&config);
+        let commands = [("web", "server"), ("worker", "jobs")]
+            .iter()
+            .map(|(name, command)| (name.to_string(), command.to_string()))
+            .collect();
+        let app_config = crate::config::App {
+            name: "multi".to_string(),
+            command_config: crate::config::CommandConfig::Commands(commands),
+            aliases: vec![Alias::try_from("*.tenants".to_string()).unwrap()],
+            per_process_ports: true,
+            ..Default::default()
+        };
+        let app = manager.add_app(app_config);
+
+        // A tenant named like a process still goes to the web process
+        let subdomain = process_subdomain("worker.tenants.test", &app);
+        assert_eq!(None, subdomain);
+        assert_eq!(
+            Backend::Port(PORT_START),
+            app.backend_for_subdomain(subdomain).await
+        );
+
+        let subdomain = process_subdomain("worker.multi.test", &app);
+        assert_eq!(Some("worker"), subdomain);
+        assert_eq!(
+            Backend::Port(PORT_START + 1),
+            app.backend_for_subdomain(subdomain).await
+        );
+    }
+
+    #[tokio::test]
+    async fn caches_app_configs() {
+        let tmp = crate::test_utils::temp_dir();
+        let app_dir = tmp.join("apps");
+        std::fs::create_dir(&app_dir).unwrap();
+        let write_app = |name: &str, command: &str| {
+            let contents = format!(
+                "name = '{}'\ndirectory = '~'\ncommand = '{}'",
+                name, command
+            );
+            std::fs::write(app_dir.join(format!("{}.toml", name)), contents).unwrap();
+        };
+        write_app("first", "server");
+
+        let mut config = Config::default();
+        config.general.config_dir = tmp.to_path_buf();
+        let manager = ProcessManager::new(&config);
+
+        let configs = manager.app_configs().await;
+        assert_eq!(1, configs.len());
+        assert!(Arc::ptr_eq(&configs, &manager.app_configs().await));
+
+        write_app("second", "server");
+        assert_eq!(2, manager.app_configs().await.len());
+    }
+
+    #[tokio::test]
+    async fn sees_edits_to_apps_that_are_not_running() {
+        let tmp = crate::test_utils::temp_dir();
+        let app_dir = tmp.join("apps");
+        std::fs::create_dir(&app_dir).unwrap();
+        let app_file = app_dir.join("first.toml");
+        std::fs::write(
+            &app_file,
+            "name = 'first'\ndirectory = '~'\ncommand = 'server'",
+        )
+        .unwrap();
+
+        let mut config = Config::default();
+        config.general.config_dir = tmp.to_path_buf();
+        let manager = ProcessManager::new(&config);
+        assert_eq!(1, manager.app_configs().await.len());
+
+        std::fs::write(
+            &app_file,
+            "name = 'first'\ndirectory = '~'\ncommand = 'other'",
+        )
+        .unwrap();
+        let configs = manager.app_configs().await;
+        let first = configs.iter().find(|app| app.name == "first").unwrap();
+        assert_eq!(
+            crate::config::CommandConfig::Command("other".to_string()),
+            first.command_config
+        );
+    }
 }
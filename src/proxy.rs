@@ -1,33 +1,82 @@
+use std::fmt::Display;
 use std::future::Future;
 use std::net::{SocketAddr, TcpListener};
 
+use futures::future::BoxFuture;
+use hyper::header::{HeaderValue, HOST};
+use hyper::server::conn::{AddrStream, Http};
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{client::HttpConnector, Body, Client, Request, Response, Server, Uri};
+use hyper::upgrade::Upgraded;
+use hyper::{Body, Request, Response, Server, StatusCode, Uri};
+use once_cell::sync::OnceCell;
+use tokio::time::timeout;
 use url::Url;
+use uuid::Uuid;
 
+use crate::backend::Backend;
+use crate::config::{ProxyConfig, UpstreamProtocol};
+use crate::header_rules::TemplateContext;
 use crate::host_resolver;
+use crate::inspector::UpstreamError;
+use faults::Injected;
+use forwarding::ClientInfo;
+use upstream::{SharedUpstream, Upstream};
 
 mod autostart_response;
+mod external;
+mod fastcgi;
+mod faults;
+mod forward_proxy;
+mod forwarding;
 mod host_missing;
+mod https;
 mod meta_server;
+mod routing;
+mod startup;
+mod static_files;
+mod upgrade;
+mod upstream;
+mod url_rewrite;
 
-use crate::{app::App, config::Config, process_manager::ProcessManager};
+use crate::{app::App, config::Config, process_manager::ProcessManager, tls::CertificateAuthority};
 
 const ERROR_MESSAGE: &str = "No response from server";
 
-async fn error_response(error: &hyper::Error, app: &App) -> Response<Body> {
-    eprintln!("Request to backend failed with error \"{}\"", error);
+/// Connections to apps, shared with requests that don't come from a client such as replays
+static UPSTREAM: OnceCell<SharedUpstream> = OnceCell::new();
 
-    if app.is_running().await {
+async fn error_response(error: impl Display, app: &App) -> Response<Body> {
+    eprintln!("Request to backend failed with error \"{:#}\"", error);
+
+    let mut response = backend_error_response(&error, app).await;
+    response
+        .extensions_mut()
+        .insert(UpstreamError(format!("{:#}", error)));
+
+    response
+}
+
+async fn backend_error_response(error: &impl Display, app: &App) -> Response<Body> {
+    if app.is_external() {
+        let body = Body::from(format!("Couldn't reach upstream server: {:#}", error));
+        Response::builder()
+            .status(StatusCode::BAD_GATEWAY)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(body)
+            .unwrap()
+    } else if app.is_running().await {
         let body = Body::from(ERROR_MESSAGE);
         Response::builder()
             .header("Content-Type", "text/plain; charset=utf-8")
             .body(body)
             .unwrap()
+    } else if let Some(response) = startup::crash_loop_response(app).await {
+        response
     } else {
+        let last_crash = app.last_crash().await;
         app.start().await;
 
-        autostart_response::autostart_response()
+        autostart_response::autostart_response(last_crash)
     }
 }
 
@@ -46,11 +95,36 @@ pub async fn start_server(config: Config, shutdown_handler: impl Future<Output =
 
     eprintln!("Starting proxy server on {}", addr);
 
-    let proxy = make_service_fn(|_| async move {
-        Ok::<_, eyre::Error>(service_fn(move |req| {
-            let client = Client::new();
-            handle_request(req, client)
-        }))
+    let upstream = UPSTREAM
+        .get_or_init(|| Upstream::new(&config.general))
+        .clone();
+
+    if let Some(https_port) = config.general.https_port {
+        let domains = ProcessManager::global_read().await.all_domains().await;
+        match CertificateAuthority::load_or_create(&config.general.config_dir, &domains) {
+            Ok(authority) => {
+                tokio::spawn(https::start_https_server(
+                    local_address(https_port),
+                    authority,
+                    upstream.clone(),
+                ));
+            }
+            Err(e) => eprintln!("Failed to set up certificate authority: {:#}", e),
+        }
+    }
+
+    let proxy = make_service_fn(|conn: &AddrStream| {
+        let client_info = ClientInfo {
+            remote_addr: conn.remote_addr(),
+            https: false,
+        };
+        let upstream = upstream.clone();
+
+        async move {
+            Ok::<_, eyre::Error>(service_fn(move |req| {
+                handle_request(req, upstream.clone(), client_info)
+            }))
+        }
     });
 
     let server = server
@@ -82,18 +156,62 @@ fn get_activation_socket() -> color_eyre::Result<TcpListener> {
 
 async fn handle_request(
     mut request: Request<Body>,
-    client: Client<HttpConnector>,
+    upstream: SharedUpstream,
+    client_info: ClientInfo,
 ) -> color_eyre::Result<Response<Body>> {
-    let host = request.headers().get("HOST").unwrap().to_str().unwrap();
-    eprintln!("Serving request for host {:?}", host);
-    eprintln!("Full req URI {}", request.uri());
-
-    let app = {
-        match host_resolver::resolve(&host).await {
-            Some(app) => app,
-            None => {
-                let process_manager = ProcessManager::global_read().await;
-                return Ok(host_missing::missing_host_response(host, &process_manager).await);
+    // HTTP/2 requests carry the host in the URI rather than a Host header
+    if !request.headers().contains_key(HOST) {
+        if let Some(authority) = request.uri().authority() {
+            if let Ok(host) = HeaderValue::from_str(authority.as_str()) {
+                request.headers_mut().insert(HOST, host);
+            }
+        }
+    }
+
+    if forward_proxy::is_proxy_request(&request) {
+        let config = proxy_config().await;
+        let result =
+            forward_proxy::handle_request(request, upstream.clone(), client_info, &config).await;
+
+        request = match result {
+            Ok(request) => request,
+            Err(response) => return Ok(response),
+        };
+    }
+
+    routing::remove_prefix_header(&mut request);
+
+    let host = request
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .map(str::to_string);
+
+    let app = match &host {
+        Some(host) => host_resolver::resolve(host).await,
+        None => None,
+    };
+    let (app, path_prefix) = match app {
+        Some(app) => (app, None),
+        None => {
+            let config = proxy_config().await;
+            if request.uri().path() == forward_proxy::PAC_PATH {
+                let domains = ProcessManager::global_read().await.all_domains().await;
+
+                return Ok(forward_proxy::pac_response(
+                    host.as_deref(),
+                    &domains,
+                    &config,
+                ));
+            }
+
+            match routing::route_by_path(&mut request, host.as_deref(), &config).await {
+                Some((app, prefix)) => (app, Some(prefix)),
+                None => {
+                    let process_manager = ProcessManager::global_read().await;
+                    let name = host.as_deref().unwrap_or_default();
+                    return Ok(host_missing::missing_host_response(name, &process_manager).await);
+                }
             }
         }
     };
@@ -102,41 +220,273 @@ async fn handle_request(
         return meta_server::handle_request(request, app).await;
     }
 
-    let destination_url = app_url(&app, request.uri());
-    *request.uri_mut() = destination_url;
+    let public_host = host.unwrap_or_else(|| "localhost".to_string());
+    let origin = format!("{}://{}", client_info.proto(), public_host);
+    // Subdomains only select a process for apps routed by host
+    let subdomain = match path_prefix {
+        Some(_) => None,
+        None => host_resolver::subdomain(&public_host, &app),
+    };
 
-    app.touch().await;
+    let capture = app.inspector().capture(
+        &mut request,
+        &format!("{}{}", origin, path_prefix.as_deref().unwrap_or_default()),
+    );
+    let mut result = proxy_request(
+        request,
+        app,
+        &public_host,
+        subdomain,
+        &upstream,
+        client_info,
+    )
+    .await;
+
+    if let (Some(prefix), Ok(response)) = (&path_prefix, &mut result) {
+        routing::prefix_redirects(response.headers_mut(), prefix, &origin);
+    }
 
-    // Apply header overrides from config
-    request.headers_mut().extend(app.headers().clone());
+    if let Some(capture) = capture {
+        match &mut result {
+            Ok(response) => capture.finish(response),
+            Err(e) => capture.fail(e),
+        }
+    }
 
-    let result = client.request(request).await;
+    result
+}
 
-    match result {
-        Ok(response) => {
-            eprintln!("Proxying response");
+/// Serve requests sent through a CONNECT tunnel to an app
+fn serve_tunnel(
+    io: Upgraded,
+    upstream: SharedUpstream,
+    client_info: ClientInfo,
+) -> BoxFuture<'static, ()> {
+    let service = service_fn(move |request| {
+        // Boxed since tunnels are opened from within `handle_request`
+        Box::pin(handle_request(request, upstream.clone(), client_info))
+            as BoxFuture<'static, color_eyre::Result<Response<Body>>>
+    });
+
+    Box::pin(async move {
+        let connection = Http::new().serve_connection(io, service).with_upgrades();
+        if let Err(e) = connection.await {
+            eprintln!("Tunneled connection failed: {}", e);
+        }
+    })
+}
+
+async fn proxy_config() -> ProxyConfig {
+    ProcessManager::global_read().await.config().general.clone()
+}
+
+async fn proxy_request(
+    mut request: Request<Body>,
+    app: App,
+    public_host: &str,
+    subdomain: Option<&str>,
+    upstream: &Upstream,
+    client_info: ClientInfo,
+) -> color_eyre::Result<Response<Body>> {
+    let fault = app.faults().find(request.uri().path()).cloned();
+    if let Some(fault) = &fault {
+        match faults::before_request(fault).await {
+            Some(Injected::Response(response)) => return Ok(response),
+            // Failing the request makes hyper drop the connection
+            Some(Injected::Reset) => eyre::bail!("Connection reset by fault rule {}", fault.name),
+            None => {}
+        }
+
+        if fault.websocket_drop_rate > 0.0 {
+            request.extensions_mut().insert(fault.clone());
+        }
+    }
+
+    // Only plain subdomains name a process, not what a wildcard or regex alias matched
+    let process_subdomain = subdomain.and(host_resolver::process_subdomain(public_host, &app));
+    let backend = app.backend_for_subdomain(process_subdomain).await;
+    let (app, backend) = match routing::route_request(app, backend, &mut request).await {
+        Ok(target) => target,
+        Err(response) => return Ok(response),
+    };
+
+    let context = template_context(&app, &backend, &client_info, subdomain).await;
+    let mut response = proxy_to_backend(
+        request,
+        upstream,
+        client_info,
+        &app,
+        backend.clone(),
+        &context,
+    )
+    .await?;
+
+    url_rewrite::rewrite_response_headers(
+        response.headers_mut(),
+        &backend,
+        app.rewrite_urls(),
+        client_info.proto(),
+        public_host,
+    );
+    app.response_headers()
+        .apply(response.headers_mut(), &context);
+
+    if let Some(bytes_per_sec) = fault.and_then(|fault| fault.bytes_per_sec) {
+        let body = std::mem::take(response.body_mut());
+        *response.body_mut() = faults::throttle(body, bytes_per_sec);
+    }
+
+    Ok(response)
+}
+
+/// Values available to header rule templates for this request
+async fn template_context(
+    app: &App,
+    backend: &Backend,
+    client_info: &ClientInfo,
+    subdomain: Option<&str>,
+) -> TemplateContext {
+    let port = match backend {
+        Backend::Port(port) => port.to_string(),
+        _ => String::new(),
+    };
+
+    TemplateContext {
+        app: app.name().to_string(),
+        process: app.process_name_for(backend).await.unwrap_or_default(),
+        port,
+        client_addr: client_info.remote_addr.ip().to_string(),
+        request_id: Uuid::new_v4().simple().to_string(),
+        subdomain: subdomain.unwrap_or_default().to_string(),
+    }
+}
+
+async fn proxy_to_backend(
+    mut request: Request<Body>,
+    upstream: &Upstream,
+    client_info: ClientInfo,
+    app: &App,
+    backend: Backend,
+    context: &TemplateContext,
+) -> color_eyre::Result<Response<Body>> {
+    if let Backend::Static { root, spa_fallback } = &backend {
+        return Ok(static_files::serve(&request, root, *spa_fallback).await);
+    }
+
+    forwarding::add_forwarding_headers(&mut request, &client_info, app.forwarded_headers());
+    forwarding::set_subdomain(&mut request, &context.subdomain, app.forwarded_headers());
+    forwarding::rewrite_host(&mut request, &backend, app.host_header());
 
-            Ok(response)
+    app.touch().await;
+
+    if app.wait_for_start() {
+        if let Err(response) = startup::wait_for_app(app, &backend).await {
+            return Ok(response);
         }
-        Err(e) => Ok(error_response(&e, &app).await),
+    }
+
+    // Apply header rules from config
+    app.request_headers().apply(request.headers_mut(), context);
+
+    let result = if app.upstream_protocol() == UpstreamProtocol::Fastcgi {
+        let document_root = app.document_root();
+        let response = fastcgi::forward(request, &backend, document_root, &client_info);
+
+        timeout(app.response_timeout(), response).await
+    } else {
+        let destination_url = app_url(&backend, request.uri());
+        *request.uri_mut() = destination_url;
+
+        timeout(
+            app.response_timeout(),
+            upstream.forward(request, &backend, app),
+        )
+        .await
+        .map(|result| result.map_err(eyre::Report::from))
+    };
+
+    match result {
+        Ok(Ok(response)) => Ok(response),
+        Ok(Err(e)) => Ok(error_response(e, app).await),
+        Err(_) => Ok(timeout_response(app)),
     }
 }
 
+fn timeout_response(app: &App) -> Response<Body> {
+    eprintln!("Request to {} timed out", app.name());
+
+    let message = format!(
+        "{} didn't respond within {} seconds",
+        app.name(),
+        app.response_timeout().as_secs()
+    );
+
+    Response::builder()
+        .status(StatusCode::GATEWAY_TIMEOUT)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .extension(UpstreamError(message.clone()))
+        .body(Body::from(message))
+        .unwrap()
+}
+
+/// Send a captured request through the proxy again
+pub(crate) async fn replay(app: &App, id: u64) -> color_eyre::Result<Response<Body>> {
+    let exchange = app
+        .inspector()
+        .get(id)
+        .ok_or_else(|| eyre::eyre!("No captured request {} for {}", id, app.name()))?;
+    let upstream = UPSTREAM
+        .get()
+        .ok_or_else(|| eyre::eyre!("Proxy server isn't running"))?
+        .clone();
+    let client_info = ClientInfo {
+        remote_addr: local_address(0),
+        https: exchange.url.starts_with("https:"),
+    };
+
+    // Boxed since meta requests can lead back here
+    Box::pin(handle_request(
+        exchange.to_request()?,
+        upstream,
+        client_info,
+    ))
+    .await
+}
+
 fn build_address(config: &Config) -> SocketAddr {
-    let port = config.general.proxy_port;
+    local_address(config.general.proxy_port)
+}
+
+fn local_address(port: u16) -> SocketAddr {
     format!("127.0.0.1:{}", port).parse().unwrap()
 }
 
-fn app_url(app: &App, request_url: &Uri) -> Uri {
+fn app_url(backend: &Backend, request_url: &Uri) -> Uri {
+    let path_and_query = request_url.path_and_query().unwrap().as_str();
+
+    let port = match backend {
+        Backend::Port(port) => *port,
+        Backend::Socket(socket) => return hyperlocal::Uri::new(socket, path_and_query).into(),
+        Backend::External { url, .. } => return external_url(url, request_url),
+        Backend::Static { .. } => unreachable!("Static apps are served by the proxy"),
+    };
+
     let base_url = Url::parse("http://localhost/").unwrap();
 
-    let mut destination_url = base_url
-        .join(request_url.path_and_query().unwrap().as_str())
-        .expect("Invalid request URL");
+    let mut destination_url = base_url.join(path_and_query).expect("Invalid request URL");
+
+    destination_url.set_port(Some(port)).unwrap();
+
+    destination_url.as_str().parse().unwrap()
+}
 
-    destination_url.set_port(Some(app.port())).unwrap();
+/// Append the request path to the upstream URL, keeping any path prefix it has
+fn external_url(upstream: &Url, request_url: &Uri) -> Uri {
+    let mut destination_url = upstream.clone();
 
-    eprintln!("Starting request to backend {}", destination_url);
+    let base_path = upstream.path().trim_end_matches('/');
+    destination_url.set_path(&format!("{}{}", base_path, request_url.path()));
+    destination_url.set_query(request_url.query());
 
     destination_url.as_str().parse().unwrap()
 }
@@ -170,11 +520,35 @@ mod tests {
             port: Some(42),
             ..Default::default()
         };
-        let app = App::from_config(&config, 0, "test".to_string());
+        let app = App::from_config(&config, 0, &Default::default());
         let source_uri = "http://testapp.test/path?query=true".parse().unwrap();
 
-        let result = app_url(&app, &source_uri);
+        let result = app_url(&Backend::Port(app.port()), &source_uri);
 
         assert_eq!(result, "http://localhost:42/path?query=true")
     }
+
+    #[test]
+    fn app_url_external_test() {
+        let source_uri = "http://staging.test/users?page=2".parse().unwrap();
+        let backend = Backend::External {
+            url: Url::parse("https://staging.example.com/api/").unwrap(),
+            insecure: false,
+        };
+
+        let result = app_url(&backend, &source_uri);
+
+        assert_eq!(result, "https://staging.example.com/api/users?page=2")
+    }
+
+    #[test]
+    fn app_url_socket_test() {
+        let source_uri = "http://testapp.test/path?query=true".parse().unwrap();
+        let backend = Backend::Socket("/tmp/testapp.sock".into());
+
+        let result = app_url(&backend, &source_uri);
+
+        assert_eq!(result.scheme_str(), Some("unix"));
+        assert_eq!(result.path_and_query().unwrap(), "/path?query=true");
+    }
 }
@@ -1,12 +1,25 @@
 use hyper::{Body, Response};
 
+use super::static_files::escape_html;
+
 const RESTART_RESPONSE: &str = include_str!(concat!(
     env!("CARGO_MANIFEST_DIR"),
     "/static/restart_response.html"
 ));
 
-pub fn autostart_response() -> Response<Body> {
-    let body = Body::from(RESTART_RESPONSE);
+/// Page shown while the app starts, explaining how its last run ended if it crashed
+pub fn autostart_response(last_crash: Option<String>) -> Response<Body> {
+    let html = match last_crash {
+        Some(last_crash) => RESTART_RESPONSE.replace(
+            "<!-- last crash -->",
+            &format!(
+                "<pre class=\"last-crash\">{}</pre>",
+                escape_html(&last_crash)
+            ),
+        ),
+        None => RESTART_RESPONSE.to_string(),
+    };
+    let body = Body::from(html);
 
     Response::builder()
         .header("Content-Type", "text/html; charset=utf-8")
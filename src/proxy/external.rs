@@ -0,0 +1,74 @@
+// HTTP(S) client for upstream apps that oxidux doesn't manage
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use hyper::client::{self, HttpConnector};
+use hyper::Client;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, ClientConfig, RootCertStore, ServerName};
+
+pub(crate) type ExternalClient = Client<HttpsConnector<HttpConnector>>;
+
+/// Build a client for external upstreams, optionally skipping certificate verification
+pub(crate) fn build_client(
+    builder: &client::Builder,
+    http: HttpConnector,
+    insecure: bool,
+) -> ExternalClient {
+    let config = if insecure {
+        insecure_config()
+    } else {
+        verified_config()
+    };
+
+    let connector = HttpsConnectorBuilder::new()
+        .with_tls_config(config)
+        .https_or_http()
+        .enable_http1()
+        .wrap_connector(http);
+
+    builder.build(connector)
+}
+
+fn verified_config() -> ClientConfig {
+    let mut roots = RootCertStore::empty();
+
+    match rustls_native_certs::load_native_certs() {
+        Ok(certs) => {
+            for cert in certs {
+                roots.add(&Certificate(cert.0)).ok();
+            }
+        }
+        Err(e) => eprintln!("Failed to load system root certificates: {}", e),
+    }
+
+    ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth()
+}
+
+fn insecure_config() -> ClientConfig {
+    ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(NoVerification))
+        .with_no_client_auth()
+}
+
+/// Accepts any certificate, for upstreams using self-signed certs
+struct NoVerification;
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
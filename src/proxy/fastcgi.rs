@@ -0,0 +1,601 @@
+// Translate proxied requests to FastCGI for apps such as php-fpm
+use std::path::Path;
+
+use eyre::{bail, eyre, Context};
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, HOST, LOCATION};
+use hyper::{Body, Request, Response, StatusCode, Version};
+use percent_encoding::percent_decode_str;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+
+use super::forwarding::ClientInfo;
+use super::static_files;
+use crate::backend::Backend;
+
+const INDEX_SCRIPT: &str = "index.php";
+const SCRIPT_EXTENSION: &str = ".php";
+
+const VERSION: u8 = 1;
+const REQUEST_ID: u16 = 1;
+const MAX_CONTENT_LENGTH: usize = 0xffff;
+
+const BEGIN_REQUEST: u8 = 1;
+const END_REQUEST: u8 = 3;
+const PARAMS: u8 = 4;
+const STDIN: u8 = 5;
+const STDOUT: u8 = 6;
+const STDERR: u8 = 7;
+
+const ROLE_RESPONDER: u16 = 1;
+
+/// Request header dropped to avoid "httpoxy", see https://httpoxy.org
+const PROXY: &str = "proxy";
+
+/// Script that should handle a request path
+#[derive(Debug, PartialEq, Eq)]
+struct Script {
+    /// URL path of the script, e.g. "/blog/index.php"
+    name: String,
+    /// Remaining path after the script name
+    path_info: String,
+}
+
+/// Send the request to a FastCGI server and convert its output to an HTTP response
+///
+/// Requests for files that exist in the document root and aren't scripts are served directly.
+pub(crate) async fn forward(
+    request: Request<Body>,
+    backend: &Backend,
+    document_root: &Path,
+    client_info: &ClientInfo,
+) -> color_eyre::Result<Response<Body>> {
+    let path = request.uri().path().to_string();
+
+    if !path.contains(SCRIPT_EXTENSION) {
+        if let Some(file) = static_files::resolve_path(document_root, &path) {
+            if file.is_file() {
+                return Ok(static_files::serve(&request, document_root, false).await);
+            }
+        }
+    }
+
+    let script = resolve_script(document_root, &path);
+    let script_filename = match static_files::resolve_path(document_root, &script.name) {
+        Some(filename) => filename,
+        None => {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("Not Found"))?)
+        }
+    };
+
+    let (params, body) = build_params(
+        request,
+        document_root,
+        &script,
+        &script_filename,
+        client_info,
+    )
+    .await?;
+
+    match backend {
+        Backend::Port(port) => {
+            let stream = TcpStream::connect(("localhost", *port))
+                .await
+                .context("Failed to connect to FastCGI server")?;
+            exchange(stream, params, body).await
+        }
+        Backend::Socket(path) => {
+            let stream = UnixStream::connect(path)
+                .await
+                .context("Failed to connect to FastCGI socket")?;
+            exchange(stream, params, body).await
+        }
+        _ => bail!(
+            "FastCGI apps must listen on a port or socket, not {}",
+            backend
+        ),
+    }
+}
+
+/// Figure out which script handles `path`, falling back to the front controller
+fn resolve_script(document_root: &Path, path: &str) -> Script {
+    // Explicit script, possibly followed by extra path info
+    let mut offset = 0;
+    for segment in path.split_inclusive('/') {
+        offset += segment.len();
+
+        if segment.trim_end_matches('/').ends_with(SCRIPT_EXTENSION) {
+            let end = offset - usize::from(segment.ends_with('/'));
+            return Script {
+                name: path[..end].to_string(),
+                path_info: path[end..].to_string(),
+            };
+        }
+    }
+
+    let is_dir = static_files::resolve_path(document_root, path)
+        .map(|dir| dir.join(INDEX_SCRIPT).is_file())
+        .unwrap_or(false);
+
+    if is_dir {
+        let separator = if path.ends_with('/') { "" } else { "/" };
+
+        Script {
+            name: format!("{}{}{}", path, separator, INDEX_SCRIPT),
+            path_info: String::new(),
+        }
+    } else {
+        Script {
+            name: format!("/{}", INDEX_SCRIPT),
+            path_info: String::new(),
+        }
+    }
+}
+
+/// Request body for the FastCGI server
+enum RequestBody {
+    /// Body with a known length that can be streamed
+    Streaming(Body),
+    /// Body that had to be read in full to find its length
+    Buffered(Bytes),
+}
+
+async fn build_params(
+    request: Request<Body>,
+    document_root: &Path,
+    script: &Script,
+    script_filename: &Path,
+    client_info: &ClientInfo,
+) -> color_eyre::Result<(Vec<(String, String)>, RequestBody)> {
+    let (parts, body) = request.into_parts();
+
+    let (content_length, body) = match parts.headers.get(CONTENT_LENGTH) {
+        Some(length) => (length.to_str()?.to_string(), RequestBody::Streaming(body)),
+        None => {
+            let bytes = hyper::body::to_bytes(body).await?;
+            (bytes.len().to_string(), RequestBody::Buffered(bytes))
+        }
+    };
+
+    let host = parts
+        .headers
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or("localhost");
+    let (server_name, server_port) = match host.rsplit_once(':') {
+        Some((name, port)) => (name, port.to_string()),
+        None => (host, client_info.default_port().to_string()),
+    };
+
+    let document_root = document_root.to_string_lossy().to_string();
+    let request_uri = parts
+        .uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+
+    let mut params = vec![
+        ("GATEWAY_INTERFACE", "CGI/1.1".to_string()),
+        ("SERVER_SOFTWARE", "oxidux".to_string()),
+        ("SERVER_PROTOCOL", protocol_name(parts.version).to_string()),
+        ("SERVER_NAME", server_name.to_string()),
+        ("SERVER_PORT", server_port),
+        ("REQUEST_SCHEME", client_info.proto().to_string()),
+        ("REQUEST_METHOD", parts.method.to_string()),
+        ("REQUEST_URI", request_uri.to_string()),
+        ("QUERY_STRING", parts.uri.query().unwrap_or("").to_string()),
+        ("DOCUMENT_ROOT", document_root),
+        ("DOCUMENT_URI", decode(&script.name)),
+        ("SCRIPT_NAME", decode(&script.name)),
+        (
+            "SCRIPT_FILENAME",
+            script_filename.to_string_lossy().to_string(),
+        ),
+        ("PATH_INFO", decode(&script.path_info)),
+        ("REMOTE_ADDR", client_info.remote_addr.ip().to_string()),
+        ("CONTENT_LENGTH", content_length),
+        ("REDIRECT_STATUS", "200".to_string()),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value))
+    .collect::<Vec<_>>();
+
+    if client_info.https {
+        params.push(("HTTPS".to_string(), "on".to_string()));
+    }
+
+    if let Some(content_type) = parts.headers.get(CONTENT_TYPE) {
+        params.push((
+            "CONTENT_TYPE".to_string(),
+            content_type.to_str()?.to_string(),
+        ));
+    }
+
+    for (name, value) in parts.headers.iter() {
+        // A Proxy header would become HTTP_PROXY, which scripts mistake for proxy settings
+        if name == CONTENT_TYPE || name == CONTENT_LENGTH || name == PROXY {
+            continue;
+        }
+
+        let name = format!("HTTP_{}", name.as_str().to_uppercase().replace('-', "_"));
+        params.push((name, String::from_utf8_lossy(value.as_bytes()).to_string()));
+    }
+
+    Ok((params, body))
+}
+
+fn decode(path: &str) -> String {
+    percent_decode_str(path).decode_utf8_lossy().to_string()
+}
+
+fn protocol_name(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_2 => "HTTP/2.0",
+        _ => "HTTP/1.1",
+    }
+}
+
+/// Run a single request over the connection and start streaming back the response
+async fn exchange<S>(
+    mut stream: S,
+    params: Vec<(String, String)>,
+    body: RequestBody,
+) -> color_eyre::Result<Response<Body>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut begin = Vec::with_capacity(8);
+    begin.extend_from_slice(&ROLE_RESPONDER.to_be_bytes());
+    // Flags, zero means the server closes the connection when done
+    begin.extend_from_slice(&[0; 6]);
+    write_record(&mut stream, BEGIN_REQUEST, &begin).await?;
+
+    let encoded_params = encode_params(&params);
+    for chunk in encoded_params.chunks(MAX_CONTENT_LENGTH) {
+        write_record(&mut stream, PARAMS, chunk).await?;
+    }
+    write_record(&mut stream, PARAMS, &[]).await?;
+
+    match body {
+        RequestBody::Streaming(mut body) => {
+            while let Some(chunk) = body.data().await {
+                write_stdin(&mut stream, &chunk?).await?;
+            }
+        }
+        RequestBody::Buffered(bytes) => write_stdin(&mut stream, &bytes).await?,
+    }
+    write_record(&mut stream, STDIN, &[]).await?;
+    stream.flush().await?;
+
+    read_response(stream).await
+}
+
+async fn write_stdin(stream: &mut (impl AsyncWrite + Unpin), data: &[u8]) -> std::io::Result<()> {
+    for chunk in data.chunks(MAX_CONTENT_LENGTH) {
+        write_record(stream, STDIN, chunk).await?;
+    }
+
+    Ok(())
+}
+
+async fn write_record(
+    stream: &mut (impl AsyncWrite + Unpin),
+    record_type: u8,
+    content: &[u8],
+) -> std::io::Result<()> {
+    let padding = (8 - content.len() % 8) % 8;
+
+    let mut header = [0; 8];
+    header[0] = VERSION;
+    header[1] = record_type;
+    header[2..4].copy_from_slice(&REQUEST_ID.to_be_bytes());
+    header[4..6].copy_from_slice(&(content.len() as u16).to_be_bytes());
+    header[6] = padding as u8;
+
+    stream.write_all(&header).await?;
+    stream.write_all(content).await?;
+    stream.write_all(&[0; 8][..padding]).await
+}
+
+fn encode_params(params: &[(String, String)]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+
+    for (name, value) in params {
+        encode_length(&mut encoded, name.len());
+        encode_length(&mut encoded, value.len());
+        encoded.extend_from_slice(name.as_bytes());
+        encoded.extend_from_slice(value.as_bytes());
+    }
+
+    encoded
+}
+
+fn encode_length(buffer: &mut Vec<u8>, length: usize) {
+    if length < 0x80 {
+        buffer.push(length as u8);
+    } else {
+        buffer.extend_from_slice(&(length as u32 | 0x8000_0000).to_be_bytes());
+    }
+}
+
+/// A record received from the FastCGI server
+struct Record {
+    record_type: u8,
+    content: Vec<u8>,
+}
+
+async fn read_record(stream: &mut (impl AsyncRead + Unpin)) -> color_eyre::Result<Record> {
+    let mut header = [0; 8];
+    stream
+        .read_exact(&mut header)
+        .await
+        .context("FastCGI server closed the connection early")?;
+
+    let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let padding = header[6] as usize;
+
+    let mut content = vec![0; length + padding];
+    stream.read_exact(&mut content).await?;
+    content.truncate(length);
+
+    Ok(Record {
+        record_type: header[1],
+        content,
+    })
+}
+
+/// Read the CGI headers from stdout, then stream the rest of the output as the body
+async fn read_response<S>(mut stream: S) -> color_eyre::Result<Response<Body>>
+where
+    S: AsyncRead + Unpin + Send + 'static,
+{
+    let mut output = Vec::new();
+
+    let header_end = loop {
+        let record = read_record(&mut stream).await?;
+
+        match record.record_type {
+            STDOUT => {
+                output.extend_from_slice(&record.content);
+                if let Some(end) = find_header_end(&output) {
+                    break end;
+                }
+            }
+            STDERR => log_stderr(&record.content),
+            END_REQUEST => bail!("FastCGI response ended before headers were sent"),
+            _ => {}
+        }
+    };
+
+    let (head, rest) = output.split_at(header_end.0);
+    let mut response = parse_headers(head)?;
+    let rest = Bytes::copy_from_slice(&rest[header_end.1 - header_end.0..]);
+
+    let (mut sender, body) = Body::channel();
+    *response.body_mut() = body;
+
+    tokio::spawn(async move {
+        if !rest.is_empty() && sender.send_data(rest).await.is_err() {
+            return;
+        }
+
+        loop {
+            match read_record(&mut stream).await {
+                Ok(record) if record.record_type == STDOUT => {
+                    if record.content.is_empty() {
+                        continue;
+                    }
+                    if sender.send_data(record.content.into()).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(record) if record.record_type == STDERR => log_stderr(&record.content),
+                Ok(record) if record.record_type == END_REQUEST => return,
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Error reading FastCGI response: {:#}", e);
+                    sender.abort();
+                    return;
+                }
+            }
+        }
+    });
+
+    Ok(response)
+}
+
+/// Find the blank line after the headers, returning where it starts and ends
+fn find_header_end(output: &[u8]) -> Option<(usize, usize)> {
+    let crlf = output
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|start| (start, start + 4));
+    let lf = output
+        .windows(2)
+        .position(|window| window == b"\n\n")
+        .map(|start| (start, start + 2));
+
+    match (crlf, lf) {
+        (Some(crlf), Some(lf)) => Some(if crlf.0 < lf.0 { crlf } else { lf }),
+        (crlf, lf) => crlf.or(lf),
+    }
+}
+
+fn parse_headers(head: &[u8]) -> color_eyre::Result<Response<Body>> {
+    let mut response = Response::new(Body::empty());
+    let mut status = None;
+
+    for line in String::from_utf8_lossy(head).lines() {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| eyre!("Invalid header line from FastCGI server: {}", line))?;
+        let value = value.trim();
+
+        if name.eq_ignore_ascii_case("status") {
+            let code = value.split_whitespace().next().unwrap_or_default();
+            status = Some(StatusCode::from_bytes(code.as_bytes())?);
+        } else {
+            let header = HeaderName::from_bytes(name.trim().as_bytes())
+                .ok()
+                .zip(HeaderValue::from_bytes(value.as_bytes()).ok());
+
+            match header {
+                Some((name, value)) => {
+                    response.headers_mut().append(name, value);
+                }
+                None => eprintln!("Skipping invalid header from FastCGI server: {}", line),
+            }
+        }
+    }
+
+    *response.status_mut() = match status {
+        Some(status) => status,
+        None if response.headers().contains_key(LOCATION) => StatusCode::FOUND,
+        None => StatusCode::OK,
+    };
+
+    Ok(response)
+}
+
+fn log_stderr(content: &[u8]) {
+    for line in String::from_utf8_lossy(content).lines() {
+        eprintln!("FastCGI: {}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    #[test]
+    fn resolves_explicit_scripts() {
+        let root = test_utils::temp_dir();
+
+        assert_eq!(
+            Script {
+                name: "/admin/edit.php".to_string(),
+                path_info: "/42/title".to_string()
+            },
+            resolve_script(&root, "/admin/edit.php/42/title")
+        );
+        assert_eq!(
+            Script {
+                name: "/info.php".to_string(),
+                path_info: String::new()
+            },
+            resolve_script(&root, "/info.php")
+        );
+    }
+
+    #[test]
+    fn resolves_directory_index_and_front_controller() {
+        let root = test_utils::temp_dir();
+        std::fs::create_dir(root.join("blog")).unwrap();
+        std::fs::write(root.join("blog/index.php"), "<?php").unwrap();
+
+        assert_eq!("/blog/index.php", resolve_script(&root, "/blog").name);
+        assert_eq!("/blog/index.php", resolve_script(&root, "/blog/").name);
+        assert_eq!("/index.php", resolve_script(&root, "/users/42").name);
+    }
+
+    #[test]
+    fn encodes_long_params() {
+        let params = vec![("NAME".to_string(), "x".repeat(200))];
+
+        let encoded = encode_params(&params);
+
+        assert_eq!(4, encoded[0]);
+        assert_eq!(&[0x80, 0, 0, 200], &encoded[1..5]);
+        assert_eq!(b"NAME", &encoded[5..9]);
+        assert_eq!(1 + 4 + 4 + 200, encoded.len());
+    }
+
+    #[test]
+    fn parses_cgi_headers() {
+        let response =
+            parse_headers(b"Status: 404 Not Found\r\nContent-Type: text/html\r\nX-A: 1").unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        assert_eq!("text/html", response.headers()[CONTENT_TYPE]);
+
+        let response = parse_headers(b"Location: /login").unwrap();
+        assert_eq!(StatusCode::FOUND, response.status());
+    }
+
+    #[test]
+    fn skips_invalid_cgi_headers() {
+        let response = parse_headers("X-Name: Zoë\r\nX-Bad\x01: 1\r\nX-A: 1".as_bytes()).unwrap();
+
+        assert_eq!("Zoë".as_bytes(), response.headers()["x-name"].as_bytes());
+        assert_eq!("1", response.headers()["x-a"]);
+        assert_eq!(2, response.headers().len());
+    }
+
+    #[tokio::test]
+    async fn params_describe_the_listener() {
+        let root = test_utils::temp_dir();
+        let script = resolve_script(&root, "/index.php");
+        let client_info = ClientInfo {
+            remote_addr: "127.0.0.1:50000".parse().unwrap(),
+            https: true,
+        };
+        let request = Request::builder()
+            .header(HOST, "blog.test")
+            .header("Proxy", "http://evil.example:8080")
+            .body(Body::empty())
+            .unwrap();
+
+        let (params, _) = build_params(request, &root, &script, &root, &client_info)
+            .await
+            .unwrap();
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(param, _)| param == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        assert_eq!(Some("on"), param("HTTPS"));
+        assert_eq!(Some("https"), param("REQUEST_SCHEME"));
+        assert_eq!(Some("443"), param("SERVER_PORT"));
+        assert_eq!(Some("127.0.0.1"), param("REMOTE_ADDR"));
+        assert_eq!(None, param("HTTP_PROXY"));
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let (client, mut server) = tokio::io::duplex(1 << 16);
+
+        let server = tokio::spawn(async move {
+            let mut stdin = Vec::new();
+            loop {
+                let record = read_record(&mut server).await.unwrap();
+                if record.record_type == STDIN {
+                    if record.content.is_empty() {
+                        break;
+                    }
+                    stdin.extend(record.content);
+                }
+            }
+
+            let mut output = b"Content-Type: text/plain\r\n\r\n".to_vec();
+            output.extend(stdin);
+            write_record(&mut server, STDOUT, &output).await.unwrap();
+            write_record(&mut server, STDOUT, &[]).await.unwrap();
+            write_record(&mut server, END_REQUEST, &[0; 8])
+                .await
+                .unwrap();
+        });
+
+        let body = RequestBody::Buffered(Bytes::from_static(b"posted data"));
+        let response = exchange(client, vec![], body).await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&bytes[..], b"posted data");
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,243 @@
+// Apply configured fault rules to proxied traffic
+use std::cmp::min;
+use std::convert::TryInto;
+use std::io;
+use std::time::Duration;
+
+use hyper::body::HttpBody;
+use hyper::{Body, Response};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::faults::FaultRule;
+
+/// Injected failure that replaces the app's response
+pub(crate) enum Injected {
+    Response(Response<Body>),
+    /// Close the connection without responding
+    Reset,
+}
+
+/// Delay the request and roll for a failure, per the rule
+pub(crate) async fn before_request(rule: &FaultRule) -> Option<Injected> {
+    if let Some(latency) = rule.latency {
+        tokio::time::sleep(latency).await;
+    }
+
+    if roll(rule.reset_rate) {
+        return Some(Injected::Reset);
+    }
+
+    if roll(rule.error_rate) {
+        let response = Response::builder()
+            .status(rule.error_status)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(Body::from(format!("Injected by fault rule {}", rule.name)))
+            .unwrap();
+
+        return Some(Injected::Response(response));
+    }
+
+    None
+}
+
+fn roll(rate: f64) -> bool {
+    rate > 0.0 && rand::random::<f64>() < rate
+}
+
+/// Send a body no faster than `bytes_per_sec`, trailers included
+pub(crate) fn throttle(mut body: Body, bytes_per_sec: u64) -> Body {
+    let (mut sender, throttled) = Body::channel();
+    // Send in slices of a tenth of a second so the rate is smooth
+    let slice_size = (bytes_per_sec / 10).max(1) as usize;
+
+    tokio::spawn(async move {
+        while let Some(chunk) = body.data().await {
+            let mut chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(_) => return sender.abort(),
+            };
+
+            while !chunk.is_empty() {
+                let slice = chunk.split_to(min(slice_size, chunk.len()));
+                let delay = Duration::from_secs_f64(slice.len() as f64 / bytes_per_sec as f64);
+
+                tokio::time::sleep(delay).await;
+                if sender.send_data(slice).await.is_err() {
+                    return;
+                }
+            }
+        }
+
+        if let Ok(Some(trailers)) = body.trailers().await {
+            sender.send_trailers(trailers).await.ok();
+        }
+    });
+
+    throttled
+}
+
+/// Copy WebSocket frames from `reader` to `writer`, dropping a fraction of the data messages
+///
+/// Only complete single-frame text and binary messages are dropped, so control frames and
+/// fragmented messages always get through intact.
+pub(crate) async fn copy_frames(
+    reader: &mut (impl AsyncRead + Unpin),
+    writer: &mut (impl AsyncWrite + Unpin),
+    drop_rate: f64,
+) -> io::Result<()> {
+    let mut buffer = Vec::new();
+    let mut chunk = vec![0; 8192];
+
+    loop {
+        let header = loop {
+            if let Some(header) = FrameHeader::parse(&buffer) {
+                break header;
+            }
+
+            let read = reader.read(&mut chunk).await?;
+            if read == 0 {
+                writer.write_all(&buffer).await?;
+                return writer.shutdown().await;
+            }
+            buffer.extend_from_slice(&chunk[..read]);
+        };
+
+        let drop = header.is_single_message() && roll(drop_rate);
+        let header_bytes: Vec<u8> = buffer.drain(..header.len).collect();
+        if !drop {
+            writer.write_all(&header_bytes).await?;
+        }
+
+        let buffered = min(header.payload_len, buffer.len() as u64) as usize;
+        let payload: Vec<u8> = buffer.drain(..buffered).collect();
+        if !drop {
+            writer.write_all(&payload).await?;
+        }
+
+        let mut remaining = header.payload_len - buffered as u64;
+        while remaining > 0 {
+            let limit = min(remaining, chunk.len() as u64) as usize;
+            let read = reader.read(&mut chunk[..limit]).await?;
+            if read == 0 {
+                return writer.shutdown().await;
+            }
+            if !drop {
+                writer.write_all(&chunk[..read]).await?;
+            }
+            remaining -= read as u64;
+        }
+
+        writer.flush().await?;
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct FrameHeader {
+    fin: bool,
+    opcode: u8,
+    /// Length of the header, including the masking key
+    len: usize,
+    payload_len: u64,
+}
+
+impl FrameHeader {
+    /// Parse a frame header from the start of `data`, if enough of it has arrived
+    fn parse(data: &[u8]) -> Option<Self> {
+        let (first, second) = (*data.first()?, *data.get(1)?);
+        let masked = second & 0x80 != 0;
+
+        let (extended_len, payload_len) = match second & 0x7f {
+            126 => (2, u16::from_be_bytes([*data.get(2)?, *data.get(3)?]) as u64),
+            127 => {
+                let bytes = data.get(2..10)?;
+                (8, u64::from_be_bytes(bytes.try_into().ok()?))
+            }
+            len => (0, len as u64),
+        };
+        let len = 2 + extended_len + if masked { 4 } else { 0 };
+
+        if data.len() < len {
+            return None;
+        }
+
+        Some(Self {
+            fin: first & 0x80 != 0,
+            opcode: first & 0x0f,
+            len,
+            payload_len,
+        })
+    }
+
+    /// Text or binary frame that holds a whole message
+    fn is_single_message(&self) -> bool {
+        self.fin && (self.opcode == 1 || self.opcode == 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(opcode: u8, fin: bool, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![opcode | if fin { 0x80 } else { 0 }];
+        if payload.len() < 126 {
+            frame.push(0x80 | payload.len() as u8);
+        } else {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        }
+        frame.extend_from_slice(&[1, 2, 3, 4]);
+        frame.extend_from_slice(payload);
+
+        frame
+    }
+
+    #[test]
+    fn parses_frame_headers() {
+        let data = frame(1, true, &[0; 300]);
+
+        assert_eq!(
+            Some(FrameHeader {
+                fin: true,
+                opcode: 1,
+                len: 8,
+                payload_len: 300
+            }),
+            FrameHeader::parse(&data)
+        );
+        assert_eq!(None, FrameHeader::parse(&data[..5]));
+    }
+
+    #[tokio::test]
+    async fn drops_data_frames_only() {
+        let ping = frame(9, true, b"ping");
+        let first_fragment = frame(1, false, b"part");
+        let last_fragment = frame(0, true, b"end");
+        let mut input = frame(1, true, b"hello");
+        input.extend_from_slice(&ping);
+        input.extend_from_slice(&frame(2, true, &[7; 200]));
+        input.extend_from_slice(&first_fragment);
+        input.extend_from_slice(&last_fragment);
+
+        let mut output = Vec::new();
+        copy_frames(&mut input.as_slice(), &mut output, 1.0)
+            .await
+            .unwrap();
+        assert_eq!([ping, first_fragment, last_fragment].concat(), output);
+
+        let mut output = Vec::new();
+        copy_frames(&mut input.as_slice(), &mut output, 0.0)
+            .await
+            .unwrap();
+        assert_eq!(input, output);
+    }
+
+    #[tokio::test]
+    async fn throttles_bodies() {
+        let started = tokio::time::Instant::now();
+        let body = throttle(Body::from(vec![0; 300]), 1000);
+
+        assert_eq!(300, hyper::body::to_bytes(body).await.unwrap().len());
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+}
@@ -0,0 +1,275 @@
+// Let browsers and tools use the proxy as their HTTP proxy, as an alternative to DNS setup
+use hyper::header::{HeaderValue, CONNECTION, HOST};
+use hyper::{Body, HeaderMap, Method, Request, Response, StatusCode, Version};
+use tokio::net::TcpStream;
+
+use super::forwarding::ClientInfo;
+use super::upstream::{SharedUpstream, Upstream};
+use crate::config::{ForwardProxy, ProxyConfig};
+use crate::host_resolver;
+
+/// Where the generated proxy auto-config file is served
+pub(crate) const PAC_PATH: &str = "/proxy.pac";
+
+const PROXY_HEADERS: &[&str] = &["proxy-connection", "proxy-authorization"];
+
+/// Headers describing a single connection, which aren't passed on to the next one
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Check for a request sent to a forward proxy, with the full URL as its target
+pub(crate) fn is_proxy_request<T>(request: &Request<T>) -> bool {
+    // HTTP/2 requests always carry the full URL
+    request.method() == Method::CONNECT
+        || (request.version() != Version::HTTP_2 && request.uri().authority().is_some())
+}
+
+/// Handle a forward proxy request, unless it's for an app
+///
+/// Requests for apps get their Host header set from the URL and are returned to be proxied as
+/// usual, anything else is handled here.
+pub(crate) async fn handle_request(
+    mut request: Request<Body>,
+    upstream: SharedUpstream,
+    client_info: ClientInfo,
+    config: &ProxyConfig,
+) -> Result<Request<Body>, Response<Body>> {
+    let authority = match request.uri().authority() {
+        Some(authority) => authority.clone(),
+        None => return Err(error_response(StatusCode::BAD_REQUEST, "Missing host")),
+    };
+    // Apps can have TLDs of their own
+    let is_app = host_resolver::is_app_domain(authority.host(), &config.domains)
+        || host_resolver::resolve(authority.host()).await.is_some();
+
+    for name in PROXY_HEADERS {
+        request.headers_mut().remove(*name);
+    }
+
+    if request.method() == Method::CONNECT {
+        return Err(connect(request, is_app, upstream, client_info, config).await);
+    }
+
+    if !is_app {
+        return Err(passthrough(request, &upstream, config).await);
+    }
+
+    // The URL takes precedence over the Host header in proxy requests
+    if let Ok(host) = HeaderValue::from_str(authority.as_str()) {
+        request.headers_mut().insert(HOST, host);
+    }
+
+    Ok(request)
+}
+
+async fn passthrough(
+    mut request: Request<Body>,
+    upstream: &Upstream,
+    config: &ProxyConfig,
+) -> Response<Body> {
+    if config.forward_proxy == ForwardProxy::Refuse {
+        return refused_response();
+    }
+
+    remove_hop_by_hop_headers(request.headers_mut());
+
+    match upstream.passthrough(request).await {
+        Ok(mut response) => {
+            remove_hop_by_hop_headers(response.headers_mut());
+            response
+        }
+        Err(e) => error_response(StatusCode::BAD_GATEWAY, &e.to_string()),
+    }
+}
+
+/// Remove hop-by-hop headers, along with any others the `Connection` header lists
+fn remove_hop_by_hop_headers(headers: &mut HeaderMap) {
+    let listed: Vec<String> = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+
+    for name in listed
+        .iter()
+        .map(String::as_str)
+        .chain(HOP_BY_HOP_HEADERS.iter().copied())
+    {
+        headers.remove(name);
+    }
+    headers.remove(CONNECTION);
+}
+
+/// Open a tunnel for a CONNECT request
+///
+/// Tunnels to apps on port 443 go to the HTTPS proxy, so browsers see a certificate from the
+/// local CA. Tunnels to apps on other ports are served directly as plain HTTP.
+async fn connect(
+    request: Request<Body>,
+    is_app: bool,
+    upstream: SharedUpstream,
+    client_info: ClientInfo,
+    config: &ProxyConfig,
+) -> Response<Body> {
+    let authority = request.uri().authority().unwrap().clone();
+
+    let target = match (is_app, authority.port_u16()) {
+        (true, Some(443)) => match config.https_port {
+            Some(port) => Some(format!("127.0.0.1:{}", port)),
+            None => {
+                return error_response(
+                    StatusCode::BAD_GATEWAY,
+                    "HTTPS is disabled, set https_port to enable it",
+                )
+            }
+        },
+        (true, _) => None,
+        (false, _) if config.forward_proxy == ForwardProxy::Refuse => return refused_response(),
+        (false, _) => Some(authority.to_string()),
+    };
+
+    // Connect before responding so the client hears about failures
+    let stream = match &target {
+        Some(target) => match TcpStream::connect(target).await {
+            Ok(stream) => Some(stream),
+            Err(e) => {
+                let message = format!("Failed to connect to {}: {}", authority, e);
+                return error_response(StatusCode::BAD_GATEWAY, &message);
+            }
+        },
+        None => None,
+    };
+
+    tokio::spawn(async move {
+        let mut upgraded = match hyper::upgrade::on(request).await {
+            Ok(upgraded) => upgraded,
+            Err(e) => return eprintln!("Failed to open tunnel to {}: {}", authority, e),
+        };
+
+        match stream {
+            Some(mut stream) => {
+                tokio::io::copy_bidirectional(&mut upgraded, &mut stream)
+                    .await
+                    .ok();
+            }
+            None => super::serve_tunnel(upgraded, upstream, client_info).await,
+        }
+    });
+
+    Response::new(Body::empty())
+}
+
+/// Proxy auto-config script sending requests for apps under `domains` through `proxy_host`
+pub(crate) fn pac_response(
+    proxy_host: Option<&str>,
+    domains: &[String],
+    config: &ProxyConfig,
+) -> Response<Body> {
+    let proxy_host = match proxy_host {
+        Some(host) => host.to_string(),
+        None => format!("127.0.0.1:{}", config.proxy_port),
+    };
+
+    Response::builder()
+        .header("Content-Type", "application/x-ns-proxy-autoconfig")
+        .body(Body::from(pac_script(&proxy_host, domains)))
+        .unwrap()
+}
+
+fn pac_script(proxy_host: &str, domains: &[String]) -> String {
+    let condition = domains
+        .iter()
+        .map(|domain| format!("dnsDomainIs(host, \".{}\")", domain))
+        .collect::<Vec<_>>()
+        .join(" || ");
+
+    format!(
+        "function FindProxyForURL(url, host) {{\n  \
+           if ({}) {{\n    \
+             return \"PROXY {}\";\n  \
+           }}\n  \
+           return \"DIRECT\";\n\
+         }}\n",
+        condition, proxy_host
+    )
+}
+
+fn refused_response() -> Response<Body> {
+    error_response(StatusCode::FORBIDDEN, "Only requests for apps are proxied")
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Body::from(message.to_string()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_proxy_requests() {
+        let absolute = Request::get("http://myapp.test/path").body(()).unwrap();
+        let origin = Request::get("/path").body(()).unwrap();
+        let connect = Request::connect("myapp.test:443").body(()).unwrap();
+        let http2 = Request::get("https://myapp.test/path")
+            .version(Version::HTTP_2)
+            .body(())
+            .unwrap();
+
+        assert!(is_proxy_request(&absolute));
+        assert!(!is_proxy_request(&origin));
+        assert!(is_proxy_request(&connect));
+        assert!(!is_proxy_request(&http2));
+    }
+
+    #[test]
+    fn removes_hop_by_hop_headers() {
+        let mut request = Request::get("http://example.com/")
+            .header("Connection", "keep-alive, X-Session")
+            .header("Keep-Alive", "timeout=5")
+            .header("X-Session", "abc")
+            .header("Proxy-Connection", "keep-alive")
+            .header("Proxy-Authorization", "Basic Zm9vOmJhcg==")
+            .header("TE", "trailers")
+            .header("Trailer", "Expires")
+            .header("Upgrade", "websocket")
+            .header("Accept", "text/html")
+            .body(())
+            .unwrap();
+
+        remove_hop_by_hop_headers(request.headers_mut());
+
+        let names: Vec<_> = request.headers().keys().map(|name| name.as_str()).collect();
+        assert_eq!(vec!["accept"], names);
+    }
+
+    #[test]
+    fn generates_pac_script() {
+        let domains = vec!["test".to_string(), "localhost".to_string()];
+        let script = pac_script("localhost:8080", &domains);
+
+        assert_eq!(
+            "function FindProxyForURL(url, host) {\n  \
+               if (dnsDomainIs(host, \".test\") || dnsDomainIs(host, \".localhost\")) {\n    \
+                 return \"PROXY localhost:8080\";\n  \
+               }\n  \
+               return \"DIRECT\";\n\
+             }\n",
+            script
+        );
+    }
+}
@@ -0,0 +1,291 @@
+// Tell apps where proxied requests originally came from
+use std::net::SocketAddr;
+
+use hyper::header::{HeaderName, HeaderValue, HOST};
+use hyper::{HeaderMap, Request};
+
+use crate::backend::Backend;
+use crate::config::{ForwardedHeaders, HostHeader};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+const X_FORWARDED_PORT: &str = "x-forwarded-port";
+const X_FORWARDED_SUBDOMAIN: &str = "x-forwarded-subdomain";
+const FORWARDED: &str = "forwarded";
+
+/// Details of the connection a request arrived on
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ClientInfo {
+    pub remote_addr: SocketAddr,
+    pub https: bool,
+}
+
+impl ClientInfo {
+    pub(crate) fn proto(&self) -> &'static str {
+        if self.https {
+            "https"
+        } else {
+            "http"
+        }
+    }
+
+    pub(crate) fn default_port(&self) -> u16 {
+        if self.https {
+            443
+        } else {
+            80
+        }
+    }
+}
+
+/// Add `X-Forwarded-*` and `Forwarded` headers, appending to any sent by earlier proxies
+pub(crate) fn add_forwarding_headers<T>(
+    request: &mut Request<T>,
+    client: &ClientInfo,
+    config: &ForwardedHeaders,
+) {
+    if !config.enabled {
+        return;
+    }
+
+    let host = request
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .map(str::to_string);
+    let port = host
+        .as_deref()
+        .and_then(|host| host.rsplit_once(':'))
+        .and_then(|(_, port)| port.parse().ok())
+        .unwrap_or_else(|| client.default_port());
+    let ip = client.remote_addr.ip();
+
+    let headers = request.headers_mut();
+
+    append(
+        headers,
+        config.header_name(X_FORWARDED_FOR),
+        &ip.to_string(),
+    );
+    if let Some(host) = &host {
+        set(headers, config.header_name(X_FORWARDED_HOST), host);
+    }
+    set(
+        headers,
+        config.header_name(X_FORWARDED_PROTO),
+        client.proto(),
+    );
+    set(
+        headers,
+        config.header_name(X_FORWARDED_PORT),
+        &port.to_string(),
+    );
+
+    if config.forwarded {
+        // IPv6 addresses have to be quoted and bracketed
+        let mut element = if ip.is_ipv6() {
+            format!("for=\"[{}]\"", ip)
+        } else {
+            format!("for={}", ip)
+        };
+        if let Some(host) = &host {
+            element.push_str(&format!(";host=\"{}\"", host));
+        }
+        element.push_str(&format!(";proto={}", client.proto()));
+
+        append(headers, config.header_name(FORWARDED), &element);
+    }
+}
+
+/// Tell the app which subdomain or alias pattern part the request matched
+///
+/// Any value sent by the client is removed, so apps can trust the header.
+pub(crate) fn set_subdomain<T>(
+    request: &mut Request<T>,
+    subdomain: &str,
+    config: &ForwardedHeaders,
+) {
+    let name = config.header_name(X_FORWARDED_SUBDOMAIN);
+    if !config.enabled || subdomain.is_empty() {
+        request.headers_mut().remove(name);
+    } else {
+        set(request.headers_mut(), name, subdomain);
+    }
+}
+
+/// Point the Host header at the backend if the app is configured to, or if it's an upstream
+pub(crate) fn rewrite_host<T>(
+    request: &mut Request<T>,
+    backend: &Backend,
+    mode: Option<HostHeader>,
+) {
+    let rewrite = match mode {
+        Some(HostHeader::Rewrite) => true,
+        Some(HostHeader::Preserve) => false,
+        // Upstream servers generally expect their own hostname
+        None => matches!(backend, Backend::External { .. }),
+    };
+
+    if !rewrite {
+        return;
+    }
+
+    let host = match backend {
+        Backend::Port(port) => format!("localhost:{}", port),
+        Backend::Socket(_) => "localhost".to_string(),
+        Backend::External { url, .. } => match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return,
+        },
+        Backend::Static { .. } => return,
+    };
+
+    if let Ok(host) = HeaderValue::from_str(&host) {
+        request.headers_mut().insert(HOST, host);
+    }
+}
+
+fn set(headers: &mut HeaderMap, name: HeaderName, value: &str) {
+    if let Ok(value) = HeaderValue::from_str(value) {
+        headers.insert(name, value);
+    }
+}
+
+fn append(headers: &mut HeaderMap, name: HeaderName, value: &str) {
+    let existing = headers
+        .get_all(&name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    if existing.is_empty() {
+        set(headers, name, value);
+    } else {
+        set(headers, name, &format!("{}, {}", existing, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use url::Url;
+
+    fn client() -> ClientInfo {
+        ClientInfo {
+            remote_addr: "127.0.0.1:50000".parse().unwrap(),
+            https: true,
+        }
+    }
+
+    #[test]
+    fn adds_forwarding_headers() {
+        let mut request = Request::builder()
+            .header(HOST, "myapp.test")
+            .header("X-Forwarded-For", "10.0.0.1")
+            .body(())
+            .unwrap();
+
+        add_forwarding_headers(&mut request, &client(), &ForwardedHeaders::default());
+
+        let headers = request.headers();
+        assert_eq!("10.0.0.1, 127.0.0.1", headers["x-forwarded-for"]);
+        assert_eq!("myapp.test", headers["x-forwarded-host"]);
+        assert_eq!("https", headers["x-forwarded-proto"]);
+        assert_eq!("443", headers["x-forwarded-port"]);
+        assert_eq!(
+            "for=127.0.0.1;host=\"myapp.test\";proto=https",
+            headers[FORWARDED]
+        );
+    }
+
+    #[test]
+    fn renames_and_disables_headers() {
+        let mut request = Request::builder()
+            .header(HOST, "myapp.test:8443")
+            .body(())
+            .unwrap();
+        let config = ForwardedHeaders {
+            forwarded: false,
+            rename: vec![(
+                "x-forwarded-for".to_string(),
+                HeaderName::from_static("x-client-ip"),
+            )]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+
+        add_forwarding_headers(&mut request, &client(), &config);
+
+        let headers = request.headers();
+        assert_eq!("127.0.0.1", headers["x-client-ip"]);
+        assert_eq!("8443", headers["x-forwarded-port"]);
+        assert!(!headers.contains_key("x-forwarded-for"));
+        assert!(!headers.contains_key(FORWARDED));
+
+        let mut request = Request::builder().body(()).unwrap();
+        let config = ForwardedHeaders {
+            enabled: false,
+            ..Default::default()
+        };
+
+        add_forwarding_headers(&mut request, &client(), &config);
+
+        assert!(request.headers().is_empty());
+    }
+
+    #[test]
+    fn sets_subdomain_header() {
+        let mut request = Request::builder()
+            .header("X-Forwarded-Subdomain", "spoofed")
+            .body(())
+            .unwrap();
+        let config = ForwardedHeaders::default();
+
+        set_subdomain(&mut request, "acme", &config);
+        assert_eq!("acme", request.headers()["x-forwarded-subdomain"]);
+
+        set_subdomain(&mut request, "", &config);
+        assert!(!request.headers().contains_key("x-forwarded-subdomain"));
+
+        let mut request = Request::builder()
+            .header("X-Forwarded-Subdomain", "spoofed")
+            .body(())
+            .unwrap();
+        let config = ForwardedHeaders {
+            enabled: false,
+            ..Default::default()
+        };
+
+        set_subdomain(&mut request, "acme", &config);
+        assert!(!request.headers().contains_key("x-forwarded-subdomain"));
+    }
+
+    #[test]
+    fn rewrites_host_header() {
+        let mut request = Request::builder()
+            .header(HOST, "myapp.test")
+            .body(())
+            .unwrap();
+
+        rewrite_host(&mut request, &Backend::Port(7500), None);
+        assert_eq!("myapp.test", request.headers()[HOST]);
+
+        rewrite_host(
+            &mut request,
+            &Backend::Port(7500),
+            Some(HostHeader::Rewrite),
+        );
+        assert_eq!("localhost:7500", request.headers()[HOST]);
+
+        let upstream = Backend::External {
+            url: Url::parse("https://staging.example.com/").unwrap(),
+            insecure: false,
+        };
+        rewrite_host(&mut request, &upstream, None);
+        assert_eq!("staging.example.com", request.headers()[HOST]);
+    }
+}
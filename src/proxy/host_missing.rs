@@ -31,10 +31,14 @@ pub async fn missing_host_response(
     let mut html = String::new();
 
     html.push_str(PRELUDE);
-    html.push_str(&format!(
-        "<h1>Couldn't find app {}, did you mean one of these?</h1>",
-        app_name
-    ));
+    if app_name.is_empty() {
+        html.push_str("<h1>No app was requested, did you mean one of these?</h1>");
+    } else {
+        html.push_str(&format!(
+            "<h1>Couldn't find app {}, did you mean one of these?</h1>",
+            app_name
+        ));
+    }
     html.push_str(&process_list(process_manager).await);
     html.push_str(POSTLUDE);
 
@@ -59,10 +63,18 @@ async fn process_list(process_manager: &ProcessManager) -> String {
     table.push_str(TABLE_HEADER);
 
     for app in process_manager.apps.iter() {
-        let status = if app.is_running().await {
-            "Running"
+        let status = if app.is_external() {
+            "External".to_string()
+        } else if app.is_static() {
+            "Static".to_string()
+        } else if let Some((process, restarts)) = app.crash_looping().await {
+            format!("Crash looping ({} after {} restarts)", process, restarts)
+        } else if app.is_running().await {
+            "Running".to_string()
+        } else if let Some(delay) = app.restart_delay().await {
+            format!("Restarting in {}s", delay.as_secs())
         } else {
-            "Stopped"
+            "Stopped".to_string()
         };
 
         table.push_str(&format!(
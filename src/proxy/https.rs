@@ -0,0 +1,168 @@
+// TLS listener that terminates HTTPS using certificates from the local CA
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use eyre::{eyre, Context};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use rustls::server::Acceptor;
+use rustls::ServerConfig;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio_rustls::LazyConfigAcceptor;
+
+use super::forwarding::ClientInfo;
+use super::upstream::SharedUpstream;
+use crate::alias::Alias;
+use crate::host_resolver;
+use crate::process_manager::ProcessManager;
+use crate::tls::{CertificateAuthority, SharedAuthority};
+
+/// Hostname used for the certificate when the client doesn't send SNI, or asks for a
+/// domain Oxidux doesn't serve
+const FALLBACK_HOST: &str = "localhost";
+
+/// Configs kept before the cache is emptied, as hosts matched by patterns each get their own
+const MAX_CONFIGS: usize = 256;
+
+/// Issues and caches TLS configs, one per app
+struct CertificateStore {
+    authority: SharedAuthority,
+    configs: Mutex<HashMap<String, Arc<ServerConfig>>>,
+}
+
+impl CertificateStore {
+    /// Find or mint a TLS config with a certificate covering the requested host
+    async fn config_for_host(&self, host: &str) -> color_eyre::Result<Arc<ServerConfig>> {
+        let (key, names) = match host_resolver::resolve(host).await {
+            Some(app) => {
+                let exact = app.aliases().iter().filter_map(|alias| match alias {
+                    Alias::Exact(name) => Some(name.as_str()),
+                    _ => None,
+                });
+                let mut names: Vec<String> = std::iter::once(app.name())
+                    .chain(exact)
+                    .flat_map(|domain| {
+                        app.tlds()
+                            .iter()
+                            .map(move |tld| format!("{}.{}", domain, tld))
+                    })
+                    .flat_map(|domain| vec![format!("*.{}", domain), domain])
+                    .collect();
+
+                // Hosts matched by a pattern get a certificate of their own
+                if covers(&names, host) {
+                    (app.name().to_string(), names)
+                } else {
+                    names.push(host.to_string());
+                    (format!("{} {}", app.name(), host), names)
+                }
+            }
+            None => {
+                let domains = ProcessManager::global_read().await.all_domains().await;
+                if host_resolver::is_app_domain(host, &domains) {
+                    // Let the client reach the missing host page
+                    (host.to_string(), vec![host.to_string()])
+                } else {
+                    // Never sign certificates for real domains
+                    (FALLBACK_HOST.to_string(), vec![FALLBACK_HOST.to_string()])
+                }
+            }
+        };
+
+        let mut configs = self.configs.lock().await;
+        if let Some(config) = configs.get(&key) {
+            return Ok(config.clone());
+        }
+
+        let config = Arc::new(self.authority.server_config(names)?);
+        if configs.len() >= MAX_CONFIGS {
+            configs.clear();
+        }
+        configs.insert(key, config.clone());
+
+        Ok(config)
+    }
+}
+
+/// Check if a certificate for `names` is valid for `host`, wildcards covering a single label
+fn covers(names: &[String], host: &str) -> bool {
+    names.iter().any(|name| match name.strip_prefix("*.") {
+        Some(parent) => host
+            .split_once('.')
+            .is_some_and(|(_, rest)| rest.eq_ignore_ascii_case(parent)),
+        None => host.eq_ignore_ascii_case(name),
+    })
+}
+
+pub(crate) async fn start_https_server(
+    addr: SocketAddr,
+    authority: CertificateAuthority,
+    upstream: SharedUpstream,
+) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => return eprintln!("Failed to start HTTPS proxy on {}: {}", addr, e),
+    };
+
+    eprintln!("Starting HTTPS proxy server on {}", addr);
+
+    let store = Arc::new(CertificateStore {
+        authority: Arc::new(authority),
+        configs: Mutex::new(HashMap::new()),
+    });
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, remote_addr)) => {
+                let store = store.clone();
+                let upstream = upstream.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_connection(stream, remote_addr, &store, upstream).await {
+                        eprintln!("HTTPS connection failed: {:#}", e);
+                    }
+                });
+            }
+            Err(e) => eprintln!("Failed to accept HTTPS connection: {}", e),
+        }
+    }
+}
+
+async fn serve_connection(
+    stream: TcpStream,
+    remote_addr: SocketAddr,
+    store: &CertificateStore,
+    upstream: SharedUpstream,
+) -> color_eyre::Result<()> {
+    let handshake = LazyConfigAcceptor::new(Acceptor::default(), stream)
+        .await
+        .context("Failed to read TLS client hello")?;
+
+    let host = handshake
+        .client_hello()
+        .server_name()
+        .unwrap_or(FALLBACK_HOST)
+        .to_string();
+    let config = store.config_for_host(&host).await?;
+
+    let tls_stream = handshake
+        .into_stream(config)
+        .await
+        .map_err(|e| eyre!("TLS handshake for {} failed: {}", host, e))?;
+
+    let negotiated_h2 = tls_stream.get_ref().1.alpn_protocol() == Some(b"h2");
+
+    let client_info = ClientInfo {
+        remote_addr,
+        https: true,
+    };
+    let service = service_fn(move |req| super::handle_request(req, upstream.clone(), client_info));
+
+    Http::new()
+        .http2_only(negotiated_h2)
+        .serve_connection(tls_stream, service)
+        .with_upgrades()
+        .await
+        .context("Error serving HTTPS connection")
+}
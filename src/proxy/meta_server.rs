@@ -1,5 +1,5 @@
 use futures::StreamExt;
-use hyper::{Body, Request, Response, StatusCode};
+use hyper::{Body, Method, Request, Response, StatusCode};
 
 use crate::app::App;
 
@@ -7,11 +7,22 @@ pub async fn handle_request(
     request: Request<Body>,
     app: App,
 ) -> color_eyre::Result<Response<Body>> {
-    let action = request.uri().path().split('/').nth(2);
+    let path: Vec<_> = request.uri().path().split('/').skip(2).collect();
 
-    match action {
-        Some("status") => status_response(app).await,
-        Some("logstream") => logstream_response(app).await,
+    match path.as_slice() {
+        ["status"] => status_response(app).await,
+        ["history"] => text_response(app.history(None).await.unwrap_or_default()),
+        ["logstream"] => logstream_response(app).await,
+        ["requests"] => text_response(app.inspector().summary()),
+        ["requests.har"] => har_response(app),
+        ["requests", id] => match id.parse().ok().and_then(|id| app.inspector().get(id)) {
+            Some(exchange) => text_response(exchange.details()),
+            None => not_found_response(),
+        },
+        ["requests", id, "replay"] if request.method() == Method::POST => match id.parse() {
+            Ok(id) => replay_response(app, id).await,
+            Err(_) => not_found_response(),
+        },
         _ => not_found_response(),
     }
 }
@@ -23,7 +34,11 @@ pub fn is_meta_request<T>(request: &Request<T>) -> bool {
 async fn status_response(app: App) -> color_eyre::Result<Response<Body>> {
     let mut status = "".to_string();
 
-    for process in app.processes {
+    if app.is_external() || app.is_static() {
+        status.push_str(&format!("{}\n", app.default_backend().await));
+    }
+
+    for process in &app.processes {
         status.push_str(&format!(
             "{}: {:?}\n",
             process.name().await,
@@ -31,9 +46,38 @@ async fn status_response(app: App) -> color_eyre::Result<Response<Body>> {
         ));
     }
 
+    status.push_str(&app.faults().status());
+
     Ok(Response::new(Body::from(status)))
 }
 
+fn text_response(text: String) -> color_eyre::Result<Response<Body>> {
+    Ok(Response::builder()
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Body::from(text))?)
+}
+
+fn har_response(app: App) -> color_eyre::Result<Response<Body>> {
+    let har = serde_json::to_string_pretty(&app.inspector().har())?;
+
+    Ok(Response::builder()
+        .header("Content-Type", "application/json")
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}.har\"", app.name()),
+        )
+        .body(Body::from(har))?)
+}
+
+async fn replay_response(app: App, id: u64) -> color_eyre::Result<Response<Body>> {
+    match super::replay(&app, id).await {
+        Ok(response) => Ok(response),
+        Err(e) => Ok(Response::builder()
+            .status(StatusCode::UNPROCESSABLE_ENTITY)
+            .body(Body::from(format!("{:#}", e)))?),
+    }
+}
+
 fn not_found_response() -> color_eyre::Result<Response<Body>> {
     Ok(Response::builder()
         .status(StatusCode::NOT_FOUND)
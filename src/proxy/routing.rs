@@ -0,0 +1,219 @@
+// Path prefix routing to other processes and apps
+use hyper::header::{HeaderValue, LOCATION};
+use hyper::{Body, HeaderMap, Request, Response, StatusCode};
+
+use crate::app::App;
+use crate::backend::Backend;
+use crate::config::{ProxyConfig, RouteTarget};
+use crate::host_resolver;
+use crate::process_manager::ProcessManager;
+
+const X_FORWARDED_PREFIX: &str = "x-forwarded-prefix";
+
+/// Find the app named at the start of the path, for hosts outside the dev domains
+///
+/// This lets apps be reached at `http://localhost:<port>/<app>/` before DNS is set up. The
+/// app's prefix is stripped from the path and sent in `X-Forwarded-Prefix`.
+pub(crate) async fn route_by_path(
+    request: &mut Request<Body>,
+    host: Option<&str>,
+    config: &ProxyConfig,
+) -> Option<(App, String)> {
+    if !config.path_routing {
+        return None;
+    }
+
+    // Include the TLDs apps use instead of the global ones
+    if let Some(host) = host {
+        let domains = ProcessManager::global_read().await.all_domains().await;
+        if host_resolver::is_app_domain(host, &domains) {
+            return None;
+        }
+    }
+
+    let base = config.path_routing_prefix.trim_end_matches('/');
+    let (name, rest) = split_app_name(request.uri().path_and_query()?.as_str(), base)?;
+    let app = host_resolver::resolve_name(name).await?;
+
+    let prefix = format!("{}/{}", base, name);
+    let prefix_header = HeaderValue::from_str(&prefix).ok()?;
+    *request.uri_mut() = rest.parse().ok()?;
+    request
+        .headers_mut()
+        .insert(X_FORWARDED_PREFIX, prefix_header);
+
+    Some((app, prefix))
+}
+
+/// Drop any `X-Forwarded-Prefix` sent by the client, so apps only see one set by path routing
+pub(crate) fn remove_prefix_header<T>(request: &mut Request<T>) {
+    request.headers_mut().remove(X_FORWARDED_PREFIX);
+}
+
+/// Split "<base>/<app>/rest?query" into the app name and the remaining path
+fn split_app_name<'a>(path_and_query: &'a str, base: &str) -> Option<(&'a str, String)> {
+    let rest = path_and_query.strip_prefix(base)?.strip_prefix('/')?;
+    let name_end = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (name, rest) = rest.split_at(name_end);
+
+    if name.is_empty() {
+        return None;
+    }
+
+    let rest = if rest.starts_with('/') {
+        rest.to_string()
+    } else {
+        format!("/{}", rest)
+    };
+
+    Some((name, rest))
+}
+
+/// Keep redirects from an app routed by path under its prefix
+pub(crate) fn prefix_redirects(headers: &mut HeaderMap, prefix: &str, origin: &str) {
+    let location = match headers.get(LOCATION).and_then(|value| value.to_str().ok()) {
+        Some(location) => location,
+        None => return,
+    };
+
+    let (origin, path) = match location.strip_prefix(origin) {
+        Some(path) if path.starts_with('/') => (origin, path),
+        _ if location.starts_with('/') && !location.starts_with("//") => ("", location),
+        _ => return,
+    };
+
+    // Apps that handle X-Forwarded-Prefix already include it
+    let has_prefix = path
+        .strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(['/', '?']));
+    if has_prefix {
+        return;
+    }
+
+    if let Ok(value) = HeaderValue::from_str(&format!("{}{}{}", origin, prefix, path)) {
+        headers.insert(LOCATION, value);
+    }
+}
+
+/// Apply the app's path routes to the request
+///
+/// Returns the app and backend that should receive the request, rewriting the request path if
+/// the route strips its prefix.
+pub(crate) async fn route_request(
+    app: App,
+    backend: Backend,
+    request: &mut Request<Body>,
+) -> Result<(App, Backend), Response<Body>> {
+    let route = match app.find_route(request.uri().path()) {
+        Some(route) => route.clone(),
+        None => return Ok((app, backend)),
+    };
+
+    if route.strip_prefix {
+        let path_and_query = request
+            .uri()
+            .path_and_query()
+            .map(|path| path.as_str())
+            .unwrap_or("/");
+
+        match route.rewrite_path(path_and_query).parse() {
+            Ok(uri) => *request.uri_mut() = uri,
+            Err(_) => {
+                return Err(route_error_response(
+                    StatusCode::BAD_REQUEST,
+                    &format!(
+                        "Stripping {} from {} leaves an invalid path",
+                        route.prefix, path_and_query
+                    ),
+                ))
+            }
+        }
+    }
+
+    match route.target {
+        RouteTarget::Process(name) => match app.find_process(&name).await {
+            Some(process) => {
+                let backend = process.backend().await;
+                Ok((app, backend))
+            }
+            None => Err(route_error_response(
+                StatusCode::BAD_GATEWAY,
+                &format!("Route {} points at missing process {}", route.prefix, name),
+            )),
+        },
+        RouteTarget::App(name) => match host_resolver::resolve_name(&name).await {
+            Some(target) => {
+                let backend = target.default_backend().await;
+                Ok((target, backend))
+            }
+            None => Err(route_error_response(
+                StatusCode::BAD_GATEWAY,
+                &format!("Route {} points at missing app {}", route.prefix, name),
+            )),
+        },
+    }
+}
+
+fn route_error_response(status: StatusCode, message: &str) -> Response<Body> {
+    eprintln!("{}", message);
+
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Body::from(message.to_string()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_app_name_from_path() {
+        assert_eq!(
+            Some(("myapp", "/users?page=2".to_string())),
+            split_app_name("/myapp/users?page=2", "")
+        );
+        assert_eq!(
+            Some(("myapp", "/?page=2".to_string())),
+            split_app_name("/myapp?page=2", "")
+        );
+        assert_eq!(
+            Some(("myapp", "/".to_string())),
+            split_app_name("/apps/myapp", "/apps")
+        );
+        assert_eq!(None, split_app_name("/other/myapp", "/apps"));
+        assert_eq!(None, split_app_name("/", ""));
+    }
+
+    #[test]
+    fn removes_client_prefix_header() {
+        let mut request = Request::builder()
+            .header("X-Forwarded-Prefix", "/admin")
+            .body(())
+            .unwrap();
+
+        remove_prefix_header(&mut request);
+
+        assert!(!request.headers().contains_key(X_FORWARDED_PREFIX));
+    }
+
+    #[test]
+    fn prefixes_redirects() {
+        let redirect = |location: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(LOCATION, location.parse().unwrap());
+            prefix_redirects(&mut headers, "/myapp", "http://localhost:8080");
+            headers[LOCATION].to_str().unwrap().to_string()
+        };
+
+        assert_eq!("/myapp/login", redirect("/login"));
+        assert_eq!(
+            "http://localhost:8080/myapp/login",
+            redirect("http://localhost:8080/login")
+        );
+        assert_eq!("/myapp/login", redirect("/myapp/login"));
+        assert_eq!("//cdn.example.com/x", redirect("//cdn.example.com/x"));
+        assert_eq!("https://example.com/", redirect("https://example.com/"));
+    }
+}
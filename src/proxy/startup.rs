@@ -0,0 +1,123 @@
+// Hold requests until a starting app is accepting connections
+use std::time::Duration;
+
+use hyper::header::{CONTENT_TYPE, RETRY_AFTER};
+use hyper::{Body, Response, StatusCode};
+use tokio::time::{sleep, timeout};
+
+use crate::app::App;
+use crate::backend::Backend;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const RETRY_AFTER_SECS: u64 = 5;
+
+/// Make sure the app is listening on `backend`, starting it and waiting if needed
+///
+/// Returns a 503 response if the app doesn't come up before its start timeout.
+pub(crate) async fn wait_for_app(app: &App, backend: &Backend) -> Result<(), Response<Body>> {
+    if backend.is_listening().await {
+        return Ok(());
+    }
+
+    if let Some(response) = crash_loop_response(app).await {
+        return Err(response);
+    }
+
+    if !app.is_running().await {
+        app.start().await;
+    }
+
+    match timeout(app.start_timeout(), wait_for_backend(backend)).await {
+        Ok(()) => Ok(()),
+        Err(_) => {
+            eprintln!("Timed out waiting for {} to start", app.name());
+            Err(unavailable_response(app).await)
+        }
+    }
+}
+
+async fn wait_for_backend(backend: &Backend) {
+    while !backend.is_listening().await {
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+async fn unavailable_response(app: &App) -> Response<Body> {
+    let mut message = format!(
+        "{} didn't start within {} seconds\n",
+        app.name(),
+        app.start_timeout().as_secs()
+    );
+    if let Some(last_crash) = app.last_crash().await {
+        message.push_str(&last_crash);
+    }
+
+    service_unavailable(message)
+}
+
+/// A 503 explaining that the app's process keeps exiting, instead of starting it again
+pub(crate) async fn crash_loop_response(app: &App) -> Option<Response<Body>> {
+    let (process, restarts) = app.crash_looping().await?;
+    let mut message = format!(
+        "{} kept exiting and was given up on after {} restarts. Check its output, then run \
+         `oxidux restart` to start it again.\n",
+        process, restarts
+    );
+    if let Some(last_crash) = app.last_crash().await {
+        message.push_str(&last_crash);
+    }
+
+    Some(service_unavailable(message))
+}
+
+fn service_unavailable(message: String) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(RETRY_AFTER, RETRY_AFTER_SECS)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::from(message))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+
+    fn app_on_port(port: u16) -> App {
+        let config = config::App {
+            name: "startup".to_string(),
+            command_config: config::CommandConfig::Commands(Default::default()),
+            port: Some(port),
+            wait_for_start: true,
+            start_timeout_secs: Some(0),
+            ..Default::default()
+        };
+
+        App::from_config(&config, 0, &Default::default())
+    }
+
+    #[tokio::test]
+    async fn passes_through_listening_app() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = app_on_port(port);
+
+        assert!(wait_for_app(&app, &Backend::Port(port)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn returns_unavailable_on_timeout() {
+        // Grab a free port and release it so nothing is listening
+        let port = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let app = app_on_port(port);
+
+        let response = wait_for_app(&app, &Backend::Port(port)).await.unwrap_err();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "5");
+    }
+}
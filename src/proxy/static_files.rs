@@ -0,0 +1,471 @@
+// Serve static app directories straight from the proxy
+use std::io::SeekFrom;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_stream::stream;
+use hyper::body::Bytes;
+use hyper::header::{
+    ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE,
+    IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, LOCATION, RANGE,
+};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+const INDEX_FILE: &str = "index.html";
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Characters escaped in directory listing links
+const HREF_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Respond to a request with a file from `root`
+///
+/// Directories are served by their index file if present, or a listing otherwise. With
+/// `spa_fallback` paths that don't exist are served the root index file.
+pub(crate) async fn serve(
+    request: &Request<Body>,
+    root: &Path,
+    spa_fallback: bool,
+) -> Response<Body> {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return plain_response(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed");
+    }
+
+    let url_path = request.uri().path();
+    let path = match resolve_path(root, url_path) {
+        Some(path) => path,
+        None => return plain_response(StatusCode::NOT_FOUND, "Not Found"),
+    };
+
+    // Symlinks leading out of the root are treated as missing
+    match confine(root, &path).await {
+        Some(path) if path.is_dir() => {
+            if !url_path.ends_with('/') {
+                let location = match request.uri().query() {
+                    Some(query) => format!("{}/?{}", url_path, query),
+                    None => format!("{}/", url_path),
+                };
+                return redirect_response(&location);
+            }
+
+            match confine(root, &path.join(INDEX_FILE)).await {
+                Some(index) if index.is_file() => file_response(request, &index).await,
+                _ => listing_response(url_path, &path).await,
+            }
+        }
+        Some(path) => file_response(request, &path).await,
+        None if spa_fallback => match confine(root, &root.join(INDEX_FILE)).await {
+            Some(index) if index.is_file() => file_response(request, &index).await,
+            _ => plain_response(StatusCode::NOT_FOUND, "Not Found"),
+        },
+        None => plain_response(StatusCode::NOT_FOUND, "Not Found"),
+    }
+}
+
+/// Real location of `path`, if it exists and doesn't resolve to somewhere outside `root`
+async fn confine(root: &Path, path: &Path) -> Option<PathBuf> {
+    let root = fs::canonicalize(root).await.ok()?;
+    let path = fs::canonicalize(path).await.ok()?;
+
+    Some(path).filter(|path| path.starts_with(&root))
+}
+
+/// Map a URL path onto the filesystem, refusing anything that escapes `root`
+pub(super) fn resolve_path(root: &Path, url_path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode_str(url_path).decode_utf8().ok()?;
+    let mut path = root.to_path_buf();
+
+    for component in Path::new(decoded.as_ref()).components() {
+        match component {
+            Component::Normal(segment) => path.push(segment),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(path)
+}
+
+async fn file_response(request: &Request<Body>, path: &Path) -> Response<Body> {
+    let metadata = match fs::metadata(path).await {
+        Ok(metadata) => metadata,
+        Err(_) => return plain_response(StatusCode::NOT_FOUND, "Not Found"),
+    };
+
+    let length = metadata.len();
+    let modified = metadata.modified().ok();
+    let etag = etag(length, modified);
+
+    let builder = Response::builder()
+        .header(CONTENT_TYPE, content_type(path))
+        .header(ETAG, &etag)
+        .header(ACCEPT_RANGES, "bytes");
+    let builder = match modified {
+        Some(modified) => builder.header(LAST_MODIFIED, httpdate::fmt_http_date(modified)),
+        None => builder,
+    };
+
+    if not_modified(request, &etag, modified) {
+        return builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    let range = match requested_range(request, &etag) {
+        Some(header) => match parse_range(header, length) {
+            Some(range) => Some(range),
+            None => {
+                return Response::builder()
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(CONTENT_RANGE, format!("bytes */{}", length))
+                    .body(Body::empty())
+                    .unwrap()
+            }
+        },
+        None => None,
+    };
+
+    let (builder, start, end) = match range {
+        Some((start, end)) => (
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, length)),
+            start,
+            end + 1,
+        ),
+        None => (builder, 0, length),
+    };
+    let builder = builder.header(CONTENT_LENGTH, end - start);
+
+    if request.method() == Method::HEAD {
+        return builder.body(Body::empty()).unwrap();
+    }
+
+    match file_body(path, start, end).await {
+        Ok(body) => builder.body(body).unwrap(),
+        Err(e) => {
+            eprintln!("Failed to read {:?}: {}", path, e);
+            plain_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to read file")
+        }
+    }
+}
+
+/// Stream the file contents between `start` and `end` (exclusive)
+async fn file_body(path: &Path, start: u64, end: u64) -> std::io::Result<Body> {
+    let mut file = File::open(path).await?;
+    file.seek(SeekFrom::Start(start)).await?;
+
+    let mut remaining = end - start;
+    let chunks = stream! {
+        let mut buffer = vec![0; CHUNK_SIZE];
+
+        while remaining > 0 {
+            let limit = remaining.min(CHUNK_SIZE as u64) as usize;
+
+            match file.read(&mut buffer[..limit]).await {
+                Ok(0) => break,
+                Ok(read) => {
+                    remaining -= read as u64;
+                    yield Ok::<_, std::io::Error>(Bytes::copy_from_slice(&buffer[..read]));
+                }
+                Err(e) => {
+                    yield Err(e);
+                    break;
+                }
+            }
+        }
+    };
+
+    Ok(Body::wrap_stream(chunks))
+}
+
+fn etag(length: u64, modified: Option<SystemTime>) -> String {
+    let modified = modified
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_nanos())
+        .unwrap_or_default();
+
+    format!("\"{:x}-{:x}\"", length, modified)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn content_type(path: &Path) -> String {
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+
+    if mime.type_() == mime_guess::mime::TEXT {
+        format!("{}; charset=utf-8", mime)
+    } else {
+        mime.to_string()
+    }
+}
+
+fn not_modified<T>(request: &Request<T>, etag: &str, modified: Option<SystemTime>) -> bool {
+    let headers = request.headers();
+
+    if let Some(if_none_match) = headers.get(IF_NONE_MATCH) {
+        let if_none_match = if_none_match.to_str().unwrap_or_default();
+        return if_none_match
+            .split(',')
+            .any(|tag| tag.trim() == etag || tag.trim() == "*");
+    }
+
+    let since = headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok());
+
+    match (since, modified) {
+        // HTTP dates only have second precision
+        (Some(since), Some(modified)) => unix_secs(modified) <= unix_secs(since),
+        _ => false,
+    }
+}
+
+/// Range header value, unless an `If-Range` condition means the full file should be sent
+fn requested_range<'a, T>(request: &'a Request<T>, etag: &str) -> Option<&'a str> {
+    let headers = request.headers();
+    let range = headers.get(RANGE)?.to_str().ok()?;
+
+    match headers.get(IF_RANGE) {
+        Some(if_range) if if_range != etag => None,
+        _ => Some(range),
+    }
+}
+
+/// Parse a single byte range into inclusive start and end offsets
+///
+/// Multiple ranges aren't supported and are treated as unsatisfiable.
+fn parse_range(header: &str, length: u64) -> Option<(u64, u64)> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') || length == 0 {
+        return None;
+    }
+
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            (length.saturating_sub(suffix), length - 1)
+        }
+        (start, "") => (start.parse().ok()?, length - 1),
+        (start, end) => (
+            start.parse().ok()?,
+            end.parse::<u64>().ok()?.min(length - 1),
+        ),
+    };
+
+    if start > end || start >= length {
+        None
+    } else {
+        Some((start, end))
+    }
+}
+
+async fn listing_response(url_path: &str, directory: &Path) -> Response<Body> {
+    let mut entries = Vec::new();
+
+    if let Ok(mut dir) = fs::read_dir(directory).await {
+        while let Ok(Some(entry)) = dir.next_entry().await {
+            let mut name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type().await.map(|t| t.is_dir()).unwrap_or(false) {
+                name.push('/');
+            }
+            entries.push(name);
+        }
+    }
+    entries.sort();
+
+    let title = format!("Index of {}", escape_html(url_path));
+    let mut html = format!(
+        "<!doctype html>\n<html>\n<head><title>{}</title></head>\n<body>\n<h1>{}</h1>\n<ul>\n",
+        title, title
+    );
+    if url_path != "/" {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for name in entries {
+        let href = utf8_percent_encode(&name, HREF_ENCODE_SET);
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            href,
+            escape_html(&name)
+        ));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+
+    Response::builder()
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(html))
+        .unwrap()
+}
+
+pub(super) fn escape_html(input: &str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn redirect_response(location: &str) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::MOVED_PERMANENTLY)
+        .header(LOCATION, location)
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn plain_response(status: StatusCode, message: &'static str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::from(message))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    fn get(path: &str) -> Request<Body> {
+        Request::builder().uri(path).body(Body::empty()).unwrap()
+    }
+
+    async fn body_string(response: Response<Body>) -> String {
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn rejects_traversal() {
+        let root = Path::new("/srv/docs");
+
+        assert_eq!(None, resolve_path(root, "/../etc/passwd"));
+        assert_eq!(None, resolve_path(root, "/a/%2e%2e/%2e%2e/etc/passwd"));
+        assert_eq!(
+            Some(PathBuf::from("/srv/docs/a b/c.html")),
+            resolve_path(root, "/a%20b/./c.html")
+        );
+    }
+
+    #[test]
+    fn range_parsing() {
+        assert_eq!(Some((0, 9)), parse_range("bytes=0-9", 100));
+        assert_eq!(Some((90, 99)), parse_range("bytes=90-", 100));
+        assert_eq!(Some((90, 99)), parse_range("bytes=-10", 100));
+        assert_eq!(Some((50, 99)), parse_range("bytes=50-500", 100));
+        assert_eq!(None, parse_range("bytes=100-", 100));
+        assert_eq!(None, parse_range("bytes=0-1,5-6", 100));
+        assert_eq!(None, parse_range("items=0-1", 100));
+    }
+
+    #[tokio::test]
+    async fn serves_files_and_ranges() {
+        let root = test_utils::temp_dir();
+        std::fs::write(root.join("hello.txt"), "Hello world").unwrap();
+
+        let response = serve(&get("/hello.txt"), &root, false).await;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            "text/plain; charset=utf-8",
+            response.headers()[CONTENT_TYPE]
+        );
+        let etag = response.headers()[ETAG].clone();
+        assert_eq!("Hello world", body_string(response).await);
+
+        let request = Request::builder()
+            .uri("/hello.txt")
+            .header(RANGE, "bytes=6-")
+            .body(Body::empty())
+            .unwrap();
+        let response = serve(&request, &root, false).await;
+        assert_eq!(StatusCode::PARTIAL_CONTENT, response.status());
+        assert_eq!("bytes 6-10/11", response.headers()[CONTENT_RANGE]);
+        assert_eq!("world", body_string(response).await);
+
+        let request = Request::builder()
+            .uri("/hello.txt")
+            .header(IF_NONE_MATCH, etag)
+            .body(Body::empty())
+            .unwrap();
+        let response = serve(&request, &root, false).await;
+        assert_eq!(StatusCode::NOT_MODIFIED, response.status());
+    }
+
+    #[tokio::test]
+    async fn directories_use_index_or_listing() {
+        let root = test_utils::temp_dir();
+        std::fs::create_dir(root.join("site")).unwrap();
+        std::fs::write(root.join("site/index.html"), "<p>home</p>").unwrap();
+        std::fs::write(root.join("notes.md"), "# Notes").unwrap();
+
+        let response = serve(&get("/site"), &root, false).await;
+        assert_eq!(StatusCode::MOVED_PERMANENTLY, response.status());
+        assert_eq!("/site/", response.headers()[LOCATION]);
+
+        let response = serve(&get("/site?lang=en"), &root, false).await;
+        assert_eq!("/site/?lang=en", response.headers()[LOCATION]);
+
+        let response = serve(&get("/site/"), &root, false).await;
+        assert_eq!("<p>home</p>", body_string(response).await);
+
+        let listing = body_string(serve(&get("/"), &root, false).await).await;
+        assert!(listing.contains("<a href=\"notes.md\">notes.md</a>"));
+        assert!(listing.contains("<a href=\"site/\">site/</a>"));
+    }
+
+    #[tokio::test]
+    async fn refuses_symlinks_out_of_root() {
+        let outside = test_utils::temp_dir();
+        std::fs::write(outside.join("secret.txt"), "secret").unwrap();
+        std::fs::write(outside.join("index.html"), "<p>outside</p>").unwrap();
+
+        let root = test_utils::temp_dir();
+        std::fs::write(root.join("inside.txt"), "inside").unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.txt"), root.join("secret.txt")).unwrap();
+        std::os::unix::fs::symlink(&*outside, root.join("linked")).unwrap();
+        std::os::unix::fs::symlink(root.join("inside.txt"), root.join("alias.txt")).unwrap();
+
+        let response = serve(&get("/secret.txt"), &root, false).await;
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        let response = serve(&get("/linked/secret.txt"), &root, false).await;
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        let response = serve(&get("/linked/"), &root, false).await;
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+
+        let response = serve(&get("/alias.txt"), &root, false).await;
+        assert_eq!("inside", body_string(response).await);
+    }
+
+    #[tokio::test]
+    async fn spa_fallback() {
+        let root = test_utils::temp_dir();
+        std::fs::write(root.join("index.html"), "<p>app</p>").unwrap();
+
+        let response = serve(&get("/users/42"), &root, false).await;
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+
+        let response = serve(&get("/users/42"), &root, true).await;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("<p>app</p>", body_string(response).await);
+    }
+}
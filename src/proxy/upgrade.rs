@@ -0,0 +1,147 @@
+// Support for proxying `Connection: Upgrade` requests, such as WebSockets
+use std::time::Duration;
+
+use hyper::client::connect::Connect;
+use hyper::header::{HeaderMap, CONNECTION, UPGRADE};
+use hyper::upgrade::Upgraded;
+use hyper::{Body, Client, Request, Response, StatusCode};
+
+use super::faults;
+use crate::app::App;
+use crate::faults::FaultRule;
+
+/// How often an open upgraded connection refreshes the app's last hit time
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Check if the request is asking to switch protocols
+pub(crate) fn is_upgrade_request<T>(request: &Request<T>) -> bool {
+    let headers = request.headers();
+
+    headers.contains_key(UPGRADE) && connection_has_upgrade(headers)
+}
+
+fn connection_has_upgrade(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+}
+
+/// Forward an upgrade request to the app and splice the connections together
+///
+/// If the app agrees to switch protocols the 101 response is passed back to the client and the
+/// two upgraded streams are copied in both directions until either side closes.
+pub(crate) async fn proxy_upgrade<C>(
+    mut request: Request<Body>,
+    client: Client<C>,
+    app: App,
+) -> Result<Response<Body>, hyper::Error>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    let client_upgrade = hyper::upgrade::on(&mut request);
+    let drop_rate = request
+        .extensions()
+        .get::<FaultRule>()
+        .map_or(0.0, |fault| fault.websocket_drop_rate);
+
+    let mut response = client.request(request).await?;
+
+    if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+        return Ok(response);
+    }
+
+    let backend_upgrade = hyper::upgrade::on(&mut response);
+
+    tokio::spawn(async move {
+        match tokio::try_join!(client_upgrade, backend_upgrade) {
+            Ok((client_io, backend_io)) => splice(client_io, backend_io, app, drop_rate).await,
+            Err(e) => eprintln!("Failed to upgrade connection: {}", e),
+        }
+    });
+
+    Ok(response)
+}
+
+/// Copy data between the upgraded connections, keeping the app alive while they're open
+async fn splice(mut client_io: Upgraded, mut backend_io: Upgraded, app: App, drop_rate: f64) {
+    let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+    let copy = async {
+        if drop_rate > 0.0 {
+            copy_dropping_frames(client_io, backend_io, drop_rate).await
+        } else {
+            tokio::io::copy_bidirectional(&mut client_io, &mut backend_io)
+                .await
+                .map(|_| ())
+        }
+    };
+    tokio::pin!(copy);
+
+    loop {
+        tokio::select! {
+            result = &mut copy => {
+                if let Err(e) = result {
+                    eprintln!("Upgraded connection closed with error: {}", e);
+                }
+
+                return;
+            }
+            _ = keepalive.tick() => app.touch().await,
+        }
+    }
+}
+
+/// Copy WebSocket frames in both directions, dropping some messages for a fault rule
+async fn copy_dropping_frames(
+    client_io: Upgraded,
+    backend_io: Upgraded,
+    drop_rate: f64,
+) -> std::io::Result<()> {
+    let (mut client_read, mut client_write) = tokio::io::split(client_io);
+    let (mut backend_read, mut backend_write) = tokio::io::split(backend_io);
+
+    tokio::try_join!(
+        faults::copy_frames(&mut client_read, &mut backend_write, drop_rate),
+        faults::copy_frames(&mut backend_read, &mut client_write, drop_rate),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_websocket_upgrade() {
+        let request = Request::builder()
+            .header(CONNECTION, "keep-alive, Upgrade")
+            .header(UPGRADE, "websocket")
+            .body(())
+            .unwrap();
+
+        assert!(is_upgrade_request(&request));
+    }
+
+    #[test]
+    fn ignores_regular_requests() {
+        let request = Request::builder()
+            .header(CONNECTION, "keep-alive")
+            .body(())
+            .unwrap();
+
+        assert!(!is_upgrade_request(&request));
+    }
+
+    #[test]
+    fn requires_upgrade_header() {
+        let request = Request::builder()
+            .header(CONNECTION, "upgrade")
+            .body(())
+            .unwrap();
+
+        assert!(!is_upgrade_request(&request));
+    }
+}
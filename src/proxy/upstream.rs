@@ -0,0 +1,112 @@
+// Shared, pooled clients for sending requests to apps
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::client::{connect::Connect, HttpConnector};
+use hyper::{Body, Client, Request, Response, Version};
+use hyperlocal::UnixConnector;
+
+use super::external::{self, ExternalClient};
+use super::upgrade;
+use crate::app::App;
+use crate::backend::Backend;
+use crate::config::{ProxyConfig, UpstreamProtocol};
+
+pub(crate) type SharedUpstream = Arc<Upstream>;
+
+/// Clients for each kind of backend, created once so connections are kept alive between requests
+pub(crate) struct Upstream {
+    http: Client<HttpConnector>,
+    unix: Client<UnixConnector>,
+    h2c_http: Client<HttpConnector>,
+    h2c_unix: Client<UnixConnector>,
+    verified: ExternalClient,
+    insecure: ExternalClient,
+}
+
+impl Upstream {
+    pub(crate) fn new(config: &ProxyConfig) -> SharedUpstream {
+        let mut builder = Client::builder();
+        builder
+            .pool_max_idle_per_host(config.upstream_pool_size)
+            .pool_idle_timeout(Duration::from_secs(config.upstream_idle_timeout_secs));
+
+        let mut http = HttpConnector::new();
+        http.set_connect_timeout(Some(Duration::from_secs(
+            config.upstream_connect_timeout_secs,
+        )));
+        http.set_nodelay(true);
+
+        let mut external_http = http.clone();
+        // Let the TLS connector handle https URLs
+        external_http.enforce_http(false);
+
+        let mut h2c_builder = builder.clone();
+        h2c_builder.http2_only(true);
+
+        Arc::new(Self {
+            h2c_http: h2c_builder.build(http.clone()),
+            h2c_unix: h2c_builder.build(UnixConnector),
+            http: builder.build(http),
+            unix: builder.build(UnixConnector),
+            verified: external::build_client(&builder, external_http.clone(), false),
+            insecure: external::build_client(&builder, external_http, true),
+        })
+    }
+
+    /// Send the request to the backend, its URI should already point there
+    ///
+    /// Apps using h2c get HTTP/2 requests, response trailers (such as gRPC status) pass through.
+    /// Upgrades only exist in HTTP/1.1, so those requests are sent to h2c apps over HTTP/1.1.
+    pub(crate) async fn forward(
+        &self,
+        mut request: Request<Body>,
+        backend: &Backend,
+        app: &App,
+    ) -> Result<Response<Body>, hyper::Error> {
+        let h2c = app.upstream_protocol() == UpstreamProtocol::H2c
+            && !upgrade::is_upgrade_request(&request);
+
+        // The client's HTTP version may not be the one the app speaks
+        *request.version_mut() = if h2c {
+            Version::HTTP_2
+        } else {
+            Version::HTTP_11
+        };
+
+        match backend {
+            Backend::Port(_) if h2c => forward(request, &self.h2c_http, app).await,
+            Backend::Socket(_) if h2c => forward(request, &self.h2c_unix, app).await,
+            Backend::Port(_) => forward(request, &self.http, app).await,
+            Backend::Socket(_) => forward(request, &self.unix, app).await,
+            Backend::External { insecure: true, .. } => forward(request, &self.insecure, app).await,
+            Backend::External { .. } => forward(request, &self.verified, app).await,
+            Backend::Static { .. } => unreachable!("Static apps are served by the proxy"),
+        }
+    }
+
+    /// Send a request for a host that isn't an app on to the internet
+    pub(crate) async fn passthrough(
+        &self,
+        mut request: Request<Body>,
+    ) -> Result<Response<Body>, hyper::Error> {
+        *request.version_mut() = Version::HTTP_11;
+
+        self.verified.request(request).await
+    }
+}
+
+async fn forward<C>(
+    request: Request<Body>,
+    client: &Client<C>,
+    app: &App,
+) -> Result<Response<Body>, hyper::Error>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    if upgrade::is_upgrade_request(&request) {
+        upgrade::proxy_upgrade(request, client.clone(), app.clone()).await
+    } else {
+        client.request(request).await
+    }
+}
@@ -0,0 +1,306 @@
+// Point backend-generated URLs and cookies back at the app's public domain
+use hyper::header::{
+    Entry, HeaderName, HeaderValue, CONTENT_LOCATION, LOCATION, REFRESH, SET_COOKIE,
+};
+use hyper::HeaderMap;
+use url::{Position, Url};
+
+use crate::backend::Backend;
+use crate::config::RewriteUrls;
+
+/// Host names that refer to the local machine
+const LOCAL_HOSTS: &[&str] = &["localhost", "127.0.0.1", "[::1]", "0.0.0.0"];
+
+/// Addresses the backend might use to refer to itself
+struct BackendHosts {
+    /// Host with the port it has to be paired with, or `None` to match any port
+    hosts: Vec<(String, Option<u16>)>,
+}
+
+impl BackendHosts {
+    fn new(backend: &Backend, config: &RewriteUrls) -> Self {
+        let mut hosts = match backend {
+            Backend::Port(port) => LOCAL_HOSTS
+                .iter()
+                .map(|host| (host.to_string(), Some(*port)))
+                .collect(),
+            Backend::Socket(_) => LOCAL_HOSTS
+                .iter()
+                .map(|host| (host.to_string(), None))
+                .collect(),
+            Backend::External { url, .. } => url
+                .host_str()
+                .map(|host| (host.to_lowercase(), url.port_or_known_default()))
+                .into_iter()
+                .collect(),
+            Backend::Static { .. } => vec![],
+        };
+
+        hosts.extend(config.hosts.iter().map(|host| match host.rsplit_once(':') {
+            Some((name, port)) if port.parse::<u16>().is_ok() => {
+                (name.to_lowercase(), port.parse().ok())
+            }
+            _ => (host.to_lowercase(), None),
+        }));
+
+        Self { hosts }
+    }
+
+    fn matches_url(&self, url: &Url) -> bool {
+        let host = match url.host_str() {
+            Some(host) => host.to_lowercase(),
+            None => return false,
+        };
+        let port = url.port_or_known_default();
+
+        self.hosts
+            .iter()
+            .any(|(name, name_port)| *name == host && (name_port.is_none() || *name_port == port))
+    }
+
+    fn matches_domain(&self, domain: &str) -> bool {
+        let domain = domain.trim_start_matches('.').to_lowercase();
+
+        self.hosts.iter().any(|(name, _)| *name == domain)
+    }
+}
+
+/// Rewrite response headers that point at the backend so they use the public host instead
+///
+/// `public_host` is the Host the browser requested, so aliases are kept.
+pub(crate) fn rewrite_response_headers(
+    headers: &mut HeaderMap,
+    backend: &Backend,
+    config: &RewriteUrls,
+    scheme: &str,
+    public_host: &str,
+) {
+    let backend_hosts = BackendHosts::new(backend, config);
+    let origin = format!("{}://{}", scheme, public_host);
+
+    if config.redirects {
+        for name in &[LOCATION, CONTENT_LOCATION] {
+            rewrite_values(headers, name, |value| {
+                rewrite_url(value, &backend_hosts, &origin)
+            });
+        }
+
+        rewrite_values(headers, &REFRESH, |value| {
+            rewrite_refresh(value, &backend_hosts, &origin)
+        });
+    }
+
+    if config.cookies {
+        // Cookies are scoped by host name only
+        let public_name = strip_port(public_host);
+
+        rewrite_values(headers, &SET_COOKIE, |value| {
+            rewrite_cookie_domain(value, &backend_hosts, public_name)
+        });
+    }
+}
+
+fn rewrite_values(
+    headers: &mut HeaderMap,
+    name: &HeaderName,
+    rewrite: impl Fn(&str) -> Option<String>,
+) {
+    if let Entry::Occupied(mut entry) = headers.entry(name) {
+        for value in entry.iter_mut() {
+            let rewritten = value
+                .to_str()
+                .ok()
+                .and_then(&rewrite)
+                .and_then(|new_value| HeaderValue::from_str(&new_value).ok());
+
+            if let Some(new_value) = rewritten {
+                *value = new_value;
+            }
+        }
+    }
+}
+
+fn rewrite_url(value: &str, backend_hosts: &BackendHosts, origin: &str) -> Option<String> {
+    let url = Url::parse(value.trim()).ok()?;
+
+    if !backend_hosts.matches_url(&url) {
+        return None;
+    }
+
+    Some(format!("{}{}", origin, &url[Position::BeforePath..]))
+}
+
+/// Rewrite the URL in a Refresh header such as "5; url=http://localhost:7500/"
+fn rewrite_refresh(value: &str, backend_hosts: &BackendHosts, origin: &str) -> Option<String> {
+    let (delay, target) = value.split_once(';')?;
+    let (key, url) = target.trim().split_once('=')?;
+
+    if !key.trim().eq_ignore_ascii_case("url") {
+        return None;
+    }
+
+    let url = url.trim().trim_matches(|c| c == '"' || c == '\'');
+    let rewritten = rewrite_url(url, backend_hosts, origin)?;
+
+    Some(format!("{}; {}={}", delay, key.trim(), rewritten))
+}
+
+fn rewrite_cookie_domain(
+    value: &str,
+    backend_hosts: &BackendHosts,
+    public_name: &str,
+) -> Option<String> {
+    let mut changed = false;
+
+    let attributes: Vec<String> = value
+        .split(';')
+        .map(|attribute| match attribute.trim().split_once('=') {
+            Some((key, domain))
+                if key.trim().eq_ignore_ascii_case("domain")
+                    && backend_hosts.matches_domain(domain.trim()) =>
+            {
+                changed = true;
+                format!(" {}={}", key.trim(), public_name)
+            }
+            _ => attribute.to_string(),
+        })
+        .collect();
+
+    if changed {
+        Some(attributes.join(";"))
+    } else {
+        None
+    }
+}
+
+fn strip_port(host: &str) -> &str {
+    // Bracketed IPv6 addresses contain colons of their own
+    match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewrite(headers: &mut HeaderMap, backend: &Backend) {
+        rewrite_response_headers(
+            headers,
+            backend,
+            &RewriteUrls::default(),
+            "https",
+            "alias.test",
+        );
+    }
+
+    #[test]
+    fn rewrites_redirects_to_backend() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            LOCATION,
+            "http://localhost:7500/login?next=%2F#top".parse().unwrap(),
+        );
+        headers.insert(CONTENT_LOCATION, "http://127.0.0.1:7500/a".parse().unwrap());
+        headers.insert(
+            REFRESH,
+            "5; url=http://localhost:7500/done".parse().unwrap(),
+        );
+
+        rewrite(&mut headers, &Backend::Port(7500));
+
+        assert_eq!("https://alias.test/login?next=%2F#top", headers[LOCATION]);
+        assert_eq!("https://alias.test/a", headers[CONTENT_LOCATION]);
+        assert_eq!("5; url=https://alias.test/done", headers[REFRESH]);
+    }
+
+    #[test]
+    fn leaves_other_urls_alone() {
+        let mut headers = HeaderMap::new();
+        headers.insert(LOCATION, "http://localhost:3000/login".parse().unwrap());
+        headers.insert(CONTENT_LOCATION, "/relative".parse().unwrap());
+
+        rewrite(&mut headers, &Backend::Port(7500));
+
+        assert_eq!("http://localhost:3000/login", headers[LOCATION]);
+        assert_eq!("/relative", headers[CONTENT_LOCATION]);
+    }
+
+    #[test]
+    fn rewrites_upstream_and_configured_hosts() {
+        let backend = Backend::External {
+            url: Url::parse("https://staging.example.com/").unwrap(),
+            insecure: false,
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            LOCATION,
+            "https://staging.example.com/home".parse().unwrap(),
+        );
+
+        rewrite(&mut headers, &backend);
+
+        assert_eq!("https://alias.test/home", headers[LOCATION]);
+
+        let config = RewriteUrls {
+            hosts: vec!["app.internal".to_string()],
+            ..Default::default()
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(LOCATION, "http://app.internal:8080/x".parse().unwrap());
+
+        rewrite_response_headers(
+            &mut headers,
+            &Backend::Port(7500),
+            &config,
+            "http",
+            "myapp.test:8080",
+        );
+
+        assert_eq!("http://myapp.test:8080/x", headers[LOCATION]);
+    }
+
+    #[test]
+    fn rewrites_cookie_domains() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            SET_COOKIE,
+            "session=abc; Domain=localhost; Path=/; HttpOnly"
+                .parse()
+                .unwrap(),
+        );
+        headers.append(SET_COOKIE, "other=1; domain=.example.com".parse().unwrap());
+
+        rewrite(&mut headers, &Backend::Port(7500));
+
+        let cookies: Vec<_> = headers.get_all(SET_COOKIE).iter().collect();
+        assert_eq!(
+            vec![
+                "session=abc; Domain=alias.test; Path=/; HttpOnly",
+                "other=1; domain=.example.com"
+            ],
+            cookies
+        );
+    }
+
+    #[test]
+    fn can_be_disabled() {
+        let mut headers = HeaderMap::new();
+        headers.insert(LOCATION, "http://localhost:7500/".parse().unwrap());
+        let config = RewriteUrls {
+            redirects: false,
+            ..Default::default()
+        };
+
+        rewrite_response_headers(
+            &mut headers,
+            &Backend::Port(7500),
+            &config,
+            "http",
+            "myapp.test",
+        );
+
+        assert_eq!("http://localhost:7500/", headers[LOCATION]);
+    }
+}
@@ -0,0 +1,257 @@
+// Local certificate authority used to issue certificates for app domains
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use eyre::Context;
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, GeneralSubtree, IsCa, KeyPair, KeyUsagePurpose, NameConstraints,
+    SanType,
+};
+use rustls::{PrivateKey, ServerConfig};
+use time::{Duration, OffsetDateTime};
+
+const CA_CERT_FILE: &str = "ca.pem";
+const CA_KEY_FILE: &str = "ca-key.pem";
+const CA_NAME: &str = "Oxidux Local CA";
+
+/// Validity window for app certificates, some clients reject anything longer than 825 days
+const LEAF_VALIDITY_DAYS: i64 = 365;
+
+pub(crate) struct CertificateAuthority {
+    certificate: Certificate,
+}
+
+impl CertificateAuthority {
+    /// Load the CA from `directory`, generating a new one limited to `domains` if it doesn't exist yet
+    pub(crate) fn load_or_create(directory: &Path, domains: &[String]) -> color_eyre::Result<Self> {
+        let cert_path = cert_path(directory);
+        let key_path = directory.join(CA_KEY_FILE);
+
+        if cert_path.is_file() && key_path.is_file() {
+            let cert_pem =
+                fs::read_to_string(&cert_path).context("Failed to read CA certificate")?;
+            let key_pem = fs::read_to_string(&key_path).context("Failed to read CA key")?;
+
+            let key_pair = KeyPair::from_pem(&key_pem).context("Invalid CA key")?;
+            let params = CertificateParams::from_ca_cert_pem(&cert_pem, key_pair)
+                .context("Invalid CA certificate")?;
+            warn_about_constraints(&params, domains, directory);
+            let certificate = Certificate::from_params(params)?;
+
+            return Ok(Self { certificate });
+        }
+
+        eprintln!("Generating local certificate authority in {:?}", directory);
+
+        let certificate = Certificate::from_params(ca_params(domains))?;
+
+        write_file(&key_path, &certificate.serialize_private_key_pem(), 0o600)
+            .context("Failed to write CA key")?;
+        write_file(&cert_path, &certificate.serialize_pem()?, 0o644)
+            .context("Failed to write CA certificate")?;
+
+        Ok(Self { certificate })
+    }
+
+    /// Build a TLS config with a certificate valid for the given domain names
+    pub(crate) fn server_config(&self, names: Vec<String>) -> color_eyre::Result<ServerConfig> {
+        let leaf = Certificate::from_params(leaf_params(names))?;
+
+        let cert_chain = vec![rustls::Certificate(
+            leaf.serialize_der_with_signer(&self.certificate)?,
+        )];
+        let key = PrivateKey(leaf.serialize_private_key_der());
+
+        let mut config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(cert_chain, key)
+            .context("Failed to build TLS config")?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(config)
+    }
+}
+
+/// Shared handle to the certificate authority
+pub(crate) type SharedAuthority = Arc<CertificateAuthority>;
+
+/// Location of the CA certificate, which needs to be trusted by browsers
+pub fn cert_path(directory: &Path) -> PathBuf {
+    directory.join(CA_CERT_FILE)
+}
+
+/// Path to the CA certificate, which the server generates on its first HTTPS start
+pub fn existing_ca(directory: &Path) -> color_eyre::Result<PathBuf> {
+    let path = cert_path(directory);
+    if !path.is_file() {
+        eyre::bail!(
+            "No certificate authority in {:?} yet, start the server with https_port set to generate one",
+            directory
+        );
+    }
+
+    Ok(path)
+}
+
+/// Point out domains a loaded CA can't sign for, such as ones added after it was created
+fn warn_about_constraints(params: &CertificateParams, domains: &[String], directory: &Path) {
+    let permitted: Vec<&str> = match &params.name_constraints {
+        Some(constraints) => constraints
+            .permitted_subtrees
+            .iter()
+            .filter_map(|subtree| match subtree {
+                GeneralSubtree::DnsName(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect(),
+        None => {
+            return eprintln!(
+                "Warning: the certificate authority in {:?} can sign for any domain. \
+                 Delete {} and {} to generate one limited to the configured domains",
+                directory, CA_CERT_FILE, CA_KEY_FILE
+            )
+        }
+    };
+
+    for domain in domains {
+        if !permitted
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(domain))
+        {
+            eprintln!(
+                "Warning: the certificate authority in {:?} can't sign for .{}, so browsers will \
+                 reject its certificates. Delete {} and {} to generate a new one, then trust it",
+                directory, domain, CA_CERT_FILE, CA_KEY_FILE
+            );
+        }
+    }
+}
+
+fn ca_params(domains: &[String]) -> CertificateParams {
+    let mut params = CertificateParams::default();
+
+    let mut name = DistinguishedName::new();
+    name.push(DnType::CommonName, CA_NAME);
+    name.push(DnType::OrganizationName, "Oxidux");
+    params.distinguished_name = name;
+
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+
+    // Keep the CA from vouching for real domains, should its key ever leak
+    params.name_constraints = Some(NameConstraints {
+        permitted_subtrees: domains
+            .iter()
+            .map(|domain| GeneralSubtree::DnsName(domain.clone()))
+            .collect(),
+        excluded_subtrees: Vec::new(),
+    });
+
+    params
+}
+
+fn leaf_params(names: Vec<String>) -> CertificateParams {
+    let mut params = CertificateParams::default();
+
+    let mut name = DistinguishedName::new();
+    if let Some(first) = names.first() {
+        name.push(DnType::CommonName, first.as_str());
+    }
+    params.distinguished_name = name;
+
+    params.subject_alt_names = names.into_iter().map(SanType::DnsName).collect();
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now - Duration::days(1);
+    params.not_after = now + Duration::days(LEAF_VALIDITY_DAYS);
+
+    params
+}
+
+fn write_file(path: &Path, contents: &str, mode: u32) -> std::io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(path)?;
+
+    file.write_all(contents.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    fn domains() -> Vec<String> {
+        vec!["test".to_string()]
+    }
+
+    #[test]
+    fn ca_is_reused_between_loads() {
+        let tmp = test_utils::temp_dir();
+
+        let first = CertificateAuthority::load_or_create(&tmp, &domains()).unwrap();
+        let pem = fs::read_to_string(cert_path(&tmp)).unwrap();
+        let second = CertificateAuthority::load_or_create(&tmp, &domains()).unwrap();
+
+        assert_eq!(pem, fs::read_to_string(cert_path(&tmp)).unwrap());
+        assert_eq!(
+            first.certificate.get_key_pair().public_key_raw(),
+            second.certificate.get_key_pair().public_key_raw()
+        );
+    }
+
+    #[test]
+    fn issues_server_config() {
+        let tmp = test_utils::temp_dir();
+        let authority = CertificateAuthority::load_or_create(&tmp, &domains()).unwrap();
+
+        let config = authority
+            .server_config(vec!["app.test".to_string(), "*.app.test".to_string()])
+            .unwrap();
+
+        assert_eq!(
+            config.alpn_protocols,
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        );
+    }
+
+    #[test]
+    fn ca_is_limited_to_domains() {
+        let tmp = test_utils::temp_dir();
+        CertificateAuthority::load_or_create(&tmp, &domains()).unwrap();
+
+        let cert_pem = fs::read_to_string(cert_path(&tmp)).unwrap();
+        let key_pem = fs::read_to_string(tmp.join(CA_KEY_FILE)).unwrap();
+        let params =
+            CertificateParams::from_ca_cert_pem(&cert_pem, KeyPair::from_pem(&key_pem).unwrap())
+                .unwrap();
+
+        let constraints = params.name_constraints.unwrap();
+        assert_eq!(
+            constraints.permitted_subtrees,
+            vec![GeneralSubtree::DnsName("test".to_string())]
+        );
+    }
+
+    #[test]
+    fn existing_ca_requires_generated_ca() {
+        let tmp = test_utils::temp_dir();
+        assert!(existing_ca(&tmp).is_err());
+
+        CertificateAuthority::load_or_create(&tmp, &domains()).unwrap();
+        assert_eq!(existing_ca(&tmp).unwrap(), cert_path(&tmp));
+    }
+}
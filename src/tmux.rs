@@ -1,4 +1,5 @@
 use crate::config;
+use std::env;
 use std::path::Path;
 use tokio::process::Command;
 
@@ -8,7 +9,7 @@ type StatusResult = CmdResult<std::process::ExitStatus>;
 
 pub(crate) async fn respawn_window(session_name: &str, shell_args: &[String]) -> StatusResult {
     base_command()
-        .args(&["respawn-window", "-t", &session_name])
+        .args(["respawn-window", "-t", session_name])
         .args(shell_args)
         .status()
         .await
@@ -16,14 +17,23 @@ pub(crate) async fn respawn_window(session_name: &str, shell_args: &[String]) ->
 
 pub(crate) async fn kill_session(session_name: &str) -> OutputResult {
     base_command()
-        .args(&["kill-session", "-t", session_name])
+        .args(["kill-session", "-t", session_name])
         .output()
         .await
 }
 
 pub(crate) async fn list_sessions() -> OutputResult {
     base_command()
-        .args(&["list-sessions", "-F", "#{session_name}|#{pane_pid}"])
+        .args(["list-sessions", "-F", "#{session_name}|#{pane_pid}"])
+        .output()
+        .await
+}
+
+/// Whether the pane in a session is dead, and its exit status kept around by `remain-on-exit`
+pub(crate) async fn pane_dead_status(session_name: &str) -> OutputResult {
+    base_command()
+        .args(["display-message", "-p", "-t", session_name])
+        .arg("#{pane_dead}|#{pane_dead_status}|#{pane_dead_signal}")
         .output()
         .await
 }
@@ -31,18 +41,18 @@ pub(crate) async fn list_sessions() -> OutputResult {
 pub(crate) async fn pipe_pane(fifo_path: &Path) -> StatusResult {
     let catpipe = format!("cat >> {}", fifo_path.to_string_lossy());
 
-    base_command().args(&["pipe-pane", &catpipe]).status().await
+    base_command().args(["pipe-pane", &catpipe]).status().await
 }
 
 pub(crate) async fn new_session(session_name: &str, shell_args: &[String]) -> OutputResult {
     base_command()
-        .args(&["new-session", "-s", session_name])
-        .args(&["-d", "-P", "-F", "#{pane_pid}"])
+        .args(["new-session", "-s", session_name])
+        .args(["-d", "-P", "-F", "#{pane_pid}"])
         .args(shell_args)
-        .args(&[";", "set", "remain-on-exit", "on"])
-        .args(&[";", "set", "mouse", "on"])
-        .args(&[";", "set", "status-right", "Press C-x to disconnect"])
-        .args(&[";", "bind-key", "-n", "C-x", "detach-client"])
+        .args([";", "set", "remain-on-exit", "on"])
+        .args([";", "set", "mouse", "on"])
+        .args([";", "set", "status-right", "Press C-x to disconnect"])
+        .args([";", "bind-key", "-n", "C-x", "detach-client"])
         .output()
         .await
 }
@@ -51,10 +61,16 @@ pub(crate) async fn kill_server() -> StatusResult {
     base_command().arg("kill-server").status().await
 }
 
+/// Check for a tmux executable on the PATH
+pub(crate) fn is_installed() -> bool {
+    env::var_os("PATH")
+        .is_some_and(|path| env::split_paths(&path).any(|dir| dir.join("tmux").is_file()))
+}
+
 fn base_command() -> Command {
     let mut command = Command::new("tmux");
-    command.args(&["-L", &config::tmux_socket()]);
-    command.args(&["-f", "/dev/null"]);
+    command.args(["-L", &config::tmux_socket()]);
+    command.args(["-f", "/dev/null"]);
 
     command
 }
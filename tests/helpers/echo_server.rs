@@ -3,23 +3,35 @@
 /// This boots up a basic http server that echos back the request body and headers, which is useful
 /// for testing purposes
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Request, Response, Server};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use hyperlocal::UnixServerExt;
 use serde::Serialize;
 use std::env;
 use std::{convert::Infallible, net::SocketAddr};
 
+use hyper::header::{HeaderMap, HeaderValue, CONNECTION, UPGRADE};
+
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
-    let port = env::var("PORT")?.parse()?;
-    // Test server that we're proxying to
-    let addr = SocketAddr::from(([127, 0, 0, 1], port));
-    let make_svc = make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(handle)) });
+    // Bind to a Unix socket rather than a port if one is provided
+    let result = if let Ok(socket) = env::var("SOCKET") {
+        let make_svc = make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(handle)) });
+        let server = Server::bind_unix(socket)?.serve(make_svc);
 
-    let server = Server::bind(&addr).serve(make_svc);
+        println!("Running");
+        server.await
+    } else {
+        let port = env::var("PORT")?.parse()?;
+        // Test server that we're proxying to
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        let make_svc = make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(handle)) });
+        let server = Server::bind(&addr).serve(make_svc);
 
-    println!("Running");
+        println!("Running");
+        server.await
+    };
 
-    if let Err(e) = server.await {
+    if let Err(e) = result {
         eprintln!("Error spawning test server: {}", e);
     }
 
@@ -29,6 +41,7 @@ async fn main() -> color_eyre::Result<()> {
 #[derive(Serialize)]
 struct EchoResponse {
     url: String,
+    version: String,
     headers: std::collections::HashMap<String, String>,
     body: String,
 }
@@ -36,6 +49,7 @@ struct EchoResponse {
 impl EchoResponse {
     async fn from_request(request: &mut Request<Body>) -> Self {
         let url = request.uri().to_string();
+        let version = format!("{:?}", request.version());
         let headers = request
             .headers()
             .iter()
@@ -44,11 +58,61 @@ impl EchoResponse {
         let body =
             String::from_utf8(hyper::body::to_bytes(request).await.unwrap().to_vec()).unwrap();
 
-        Self { url, headers, body }
+        Self {
+            url,
+            version,
+            headers,
+            body,
+        }
     }
 }
 
 async fn handle(mut request: Request<Body>) -> color_eyre::Result<Response<Body>> {
+    if let Some(protocol) = request.headers().get(UPGRADE).cloned() {
+        return upgrade_response(request, protocol);
+    }
+
+    if request.uri().path() == "/trailers" {
+        return Ok(trailers_response());
+    }
+
     let response_json = serde_json::to_string(&EchoResponse::from_request(&mut request).await)?;
     Ok(Response::new(Body::from(response_json)))
 }
+
+/// Respond with a body followed by gRPC style trailers
+fn trailers_response() -> Response<Body> {
+    let (mut sender, body) = Body::channel();
+
+    tokio::spawn(async move {
+        sender.send_data("done".into()).await.ok();
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        sender.send_trailers(trailers).await.ok();
+    });
+
+    Response::new(body)
+}
+
+/// Switch protocols and echo back any bytes sent over the upgraded connection
+fn upgrade_response(
+    mut request: Request<Body>,
+    protocol: HeaderValue,
+) -> color_eyre::Result<Response<Body>> {
+    tokio::spawn(async move {
+        match hyper::upgrade::on(&mut request).await {
+            Ok(upgraded) => {
+                let (mut reader, mut writer) = tokio::io::split(upgraded);
+                tokio::io::copy(&mut reader, &mut writer).await.ok();
+            }
+            Err(e) => eprintln!("Upgrade failed: {}", e),
+        }
+    });
+
+    Ok(Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(CONNECTION, "upgrade")
+        .header(UPGRADE, protocol)
+        .body(Body::empty())?)
+}
@@ -7,6 +7,7 @@ use std::fs::{create_dir, File};
 use std::io::{BufRead, BufReader, Write};
 use std::path::PathBuf;
 use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::oneshot;
 
 #[path = "./helpers/test_utils.rs"]
@@ -20,7 +21,7 @@ async fn it_proxies_to_configured_port() {
     let app_dir = config_dir.join("apps");
     create_dir(&app_dir).unwrap();
 
-    let mut app_file = File::create(&app_dir.join("proxy_test.toml")).unwrap();
+    let mut app_file = File::create(app_dir.join("proxy_test.toml")).unwrap();
 
     app_file
         .write_all(
@@ -39,6 +40,26 @@ command = 'sleep 10; {}'
         )
         .unwrap();
 
+    // Second app that is spoken to over HTTP/2 without TLS
+    let h2c_app_name = "h2c_test";
+    let h2c_port = 9586;
+    let mut app_file = File::create(app_dir.join("h2c_test.toml")).unwrap();
+    app_file
+        .write_all(
+            format!(
+                "
+name = '{}'
+directory = '/'
+port = {}
+command = 'sleep 10'
+upstream_protocol = 'h2c'
+",
+                h2c_app_name, h2c_port
+            )
+            .as_ref(),
+        )
+        .unwrap();
+
     let (tx, rx) = oneshot::channel::<()>();
     let tld = "test";
     let proxy_port = 9584;
@@ -46,7 +67,7 @@ command = 'sleep 10; {}'
         general: ProxyConfig {
             proxy_port,
             config_dir: config_dir.to_path_buf(),
-            domain: tld.into(),
+            domains: vec![tld.into()],
             ..Default::default()
         },
     };
@@ -64,6 +85,7 @@ command = 'sleep 10; {}'
 
     // TODO: remove this and let the app autostart
     let _server = HelperCommand::run_echo_server(port).unwrap();
+    let _h2c_server = HelperCommand::run_echo_server(h2c_port).unwrap();
 
     // Send request to proxy
     let client = Client::new();
@@ -88,8 +110,80 @@ command = 'sleep 10; {}'
     let data: serde_json::Value = serde_json::from_slice(&buffer).unwrap();
 
     assert_eq!(data["url"], "/proxy-test");
+    assert_eq!(data["version"], "HTTP/1.1");
     assert_eq!(data["headers"]["host"], app_host);
     assert_eq!(data["body"], greeting);
+    assert_eq!(data["headers"]["x-forwarded-host"], app_host);
+    assert_eq!(data["headers"]["x-forwarded-for"], "127.0.0.1");
+    assert_eq!(data["headers"]["x-forwarded-proto"], "http");
+
+    // Upgraded connections should be spliced through to the app
+    let request = Request::builder()
+        .uri(&uri)
+        .header("host", app_host.clone())
+        .header("connection", "upgrade")
+        .header("upgrade", "echo")
+        .body(Body::empty())
+        .unwrap();
+
+    let response = tokio::time::timeout(Duration::from_secs(1), client.request(request))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(response.status(), 101);
+
+    let mut upgraded = hyper::upgrade::on(response).await.unwrap();
+    upgraded.write_all(b"ping").await.unwrap();
+    let mut buffer = [0; 4];
+    tokio::time::timeout(Duration::from_secs(1), upgraded.read_exact(&mut buffer))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buffer, b"ping");
+
+    // Requests to h2c apps are upgraded to HTTP/2
+    let h2c_host = format!("{}.{}", h2c_app_name, tld);
+    let request = Request::builder()
+        .uri(&uri)
+        .header("host", h2c_host.clone())
+        .body(Body::empty())
+        .unwrap();
+
+    let response = tokio::time::timeout(Duration::from_secs(1), client.request(request))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let buffer = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let data: serde_json::Value = serde_json::from_slice(&buffer).unwrap();
+
+    assert_eq!(data["version"], "HTTP/2.0");
+    assert_eq!(data["headers"]["host"], h2c_host);
+
+    // Upgrades don't exist in HTTP/2, so they reach h2c apps over HTTP/1.1
+    let request = Request::builder()
+        .uri(&uri)
+        .header("host", h2c_host.clone())
+        .header("connection", "upgrade")
+        .header("upgrade", "echo")
+        .body(Body::empty())
+        .unwrap();
+
+    let response = tokio::time::timeout(Duration::from_secs(1), client.request(request))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(response.status(), 101);
+
+    let mut upgraded = hyper::upgrade::on(response).await.unwrap();
+    upgraded.write_all(b"pong").await.unwrap();
+    let mut buffer = [0; 4];
+    tokio::time::timeout(Duration::from_secs(1), upgraded.read_exact(&mut buffer))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buffer, b"pong");
 
     tx.send(()).unwrap();
 }
@@ -103,7 +197,7 @@ impl HelperCommand {
         let helper_exe = test_process_path("echo-server");
         use std::process::{Command, Stdio};
 
-        let mut child = Command::new(&helper_exe.unwrap())
+        let mut child = Command::new(helper_exe.unwrap())
             .env("PORT", port.to_string())
             .stdout(Stdio::piped())
             .spawn()?;